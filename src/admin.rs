@@ -0,0 +1,37 @@
+use anyhow::{anyhow, Result};
+use axum::Router;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use log::{error, info};
+
+use crate::failures::FAILURES;
+use crate::metrics::METRICS;
+
+/// Starts the admin HTTP server, serving Prometheus metrics on `/metrics` and
+/// the most recent processing and write failures on `/failures`.
+pub async fn spawn(listen: &str) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", listen, e))?;
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/failures", get(failures));
+
+    info!("Admin endpoint listening on {}", listen);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Admin endpoint stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], METRICS.render())
+}
+
+async fn failures() -> impl IntoResponse {
+    let body = serde_json::to_string_pretty(&FAILURES.recent()).unwrap_or_default();
+    ([(header::CONTENT_TYPE, "application/json")], body)
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::pipeline::{Message, Point};
+use crate::presets::{Preset, point};
+use crate::topic_filter;
+
+const ONLINE: &[&str] = &["online", "true", "1", "connected"];
+const OFFLINE: &[&str] = &["offline", "false", "0", "disconnected"];
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AvailabilityConfig {
+    /// Availability topic filters, such as `+/status` or `tele/+/LWT`
+    pub topics: Vec<String>,
+    /// Defaults to `availability`
+    pub measurement: Option<String>,
+    /// Payloads meaning the device is up, compared case insensitively
+    pub online: Option<Vec<String>>,
+    /// Payloads meaning the device is down, compared case insensitively
+    pub offline: Option<Vec<String>>,
+}
+
+/// Writes 1 or 0 per device from online/offline availability topics, which
+/// are usually retained last wills so the current state arrives on subscribe.
+pub struct Availability {
+    topics: Vec<String>,
+    measurement: String,
+    online: Vec<String>,
+    offline: Vec<String>,
+}
+
+impl Availability {
+    pub fn new(config: &AvailabilityConfig) -> Self {
+        let payloads = |configured: &Option<Vec<String>>, default: &[&str]| match configured {
+            Some(payloads) => payloads.iter().map(|payload| payload.to_lowercase()).collect(),
+            None => default.iter().map(|payload| payload.to_string()).collect(),
+        };
+        Availability {
+            topics: config.topics.clone(),
+            measurement: config.measurement.clone().unwrap_or_else(|| "availability".to_string()),
+            online: payloads(&config.online, ONLINE),
+            offline: payloads(&config.offline, OFFLINE),
+        }
+    }
+}
+
+/// The device a topic belongs to: the levels matched by the filter's
+/// wildcards, or the whole topic when it has none.
+fn device<'a>(filter: &str, topic: &'a str) -> String {
+    let mut levels = topic.split('/');
+    let mut device: Vec<&'a str> = Vec::new();
+    for level in filter.split('/') {
+        match level {
+            "#" => {
+                device.extend(levels.by_ref());
+                break;
+            }
+            "+" => device.extend(levels.next()),
+            _ => {
+                levels.next();
+            }
+        }
+    }
+    if device.is_empty() { topic.to_string() } else { device.join("/") }
+}
+
+impl Preset for Availability {
+    fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    fn extract(&self, message: &Message) -> Result<Vec<Point>> {
+        let payload = String::from_utf8_lossy(&message.payload);
+        // Zigbee2MQTT publishes `{"state":"online"}` rather than a bare string
+        let state = match serde_json::from_str::<serde_json::Value>(&payload) {
+            Ok(serde_json::Value::Object(object)) => {
+                object.get("state").and_then(serde_json::Value::as_str).map(str::to_lowercase)
+            }
+            _ => Some(payload.trim().to_lowercase()),
+        };
+        let value = match state {
+            Some(state) if self.online.contains(&state) => 1.0,
+            Some(state) if self.offline.contains(&state) => 0.0,
+            _ => return Ok(Vec::new()),
+        };
+        let Some(filter) = self.topics.iter().find(|filter| topic_filter::matches(filter, &message.topic)) else {
+            return Ok(Vec::new());
+        };
+        let device = device(filter, &message.topic);
+        Ok(vec![point(&self.measurement, value, &[("device", &device)], message.received_at)])
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use std::time::{Duration, Instant};
+
+use crate::Config;
+use crate::pipeline::{Message, Pipeline};
+use crate::record::RecordingReader;
+use crate::sink::{Discard, Sink};
+
+/// Builds the benchmark corpus up front so generating it is not part of the timings.
+/// Messages come from a recording when one is given, otherwise from the
+/// `[[simulate]]` templates, repeating either source until `count` is reached.
+fn load_messages(config: &Config, count: usize, input: Option<&str>) -> Result<Vec<Message>> {
+    let source: Vec<Message> = match input {
+        Some(input) => RecordingReader::open(input)?
+            .map(|recorded| Message::from_recorded(&recorded?, false))
+            .collect::<Result<_>>()?,
+        None => {
+            let simulators = config.simulate.clone().unwrap_or_default();
+            if simulators.is_empty() {
+                return Err(anyhow!("Benchmarking needs a recording file or [[simulate]] entries in the configuration"));
+            }
+            return Ok((0..count)
+                .map(|i| {
+                    let simulator = &simulators[i % simulators.len()];
+                    Message {
+                        topic: simulator.topic.clone(),
+                        payload: Bytes::from(simulator.render()),
+                        qos: 0,
+                        retain: false,
+                        received_at: chrono::Utc::now(),
+                    }
+                })
+                .collect());
+        }
+    };
+    if source.is_empty() {
+        return Err(anyhow!("The recording contains no messages"));
+    }
+    Ok(source.iter().cycle().take(count).cloned().collect())
+}
+
+/// Runs `count` messages through the configured pipeline against a sink that
+/// discards the formatted points, then reports throughput and per-stage timings.
+pub async fn run_bench(config: &Config, count: usize, input: Option<&str>) -> Result<()> {
+    let pipeline = Pipeline::new(config)?;
+    let sink = Discard;
+    let messages = load_messages(config, count, input)?;
+
+    let mut decode = Duration::ZERO;
+    let mut extract = Duration::ZERO;
+    let mut write = Duration::ZERO;
+    let mut points = 0usize;
+    let mut errors = 0usize;
+
+    let started = Instant::now();
+    for message in &messages {
+        let stage = Instant::now();
+        let json = pipeline.decode(&message.topic, &message.payload);
+        decode += stage.elapsed();
+        let Ok(json) = json else {
+            errors += 1;
+            continue;
+        };
+
+        let stage = Instant::now();
+        let extraction = pipeline.extract_from(message, &json);
+        extract += stage.elapsed();
+        if !extraction.errors.is_empty() {
+            errors += 1;
+        }
+
+        let stage = Instant::now();
+        for point in &extraction.points {
+            sink.write(point, point.bucket.as_deref().unwrap_or_default()).await?;
+        }
+        write += stage.elapsed();
+        points += extraction.points.len();
+    }
+    let elapsed = started.elapsed();
+
+    let per_second = |n: usize| n as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    let per_message = |d: Duration| d.as_secs_f64() * 1_000_000.0 / messages.len().max(1) as f64;
+    println!(
+        "Benchmarked {} messages ({} points, {} errors) in {:.3}s",
+        messages.len(),
+        points,
+        errors,
+        elapsed.as_secs_f64()
+    );
+    println!(
+        "Throughput: {:.0} msgs/sec, {:.0} points/sec",
+        per_second(messages.len()),
+        per_second(points)
+    );
+    println!(
+        "Per message: decode {:.1}µs, extract {:.1}µs, write {:.1}µs",
+        per_message(decode),
+        per_message(extract),
+        per_message(write)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// The type of a value in a binary payload.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BinaryType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+impl BinaryType {
+    fn size(self) -> usize {
+        match self {
+            BinaryType::U8 | BinaryType::I8 => 1,
+            BinaryType::U16 | BinaryType::I16 => 2,
+            BinaryType::U32 | BinaryType::I32 | BinaryType::F32 => 4,
+            BinaryType::U64 | BinaryType::I64 | BinaryType::F64 => 8,
+        }
+    }
+}
+
+/// The byte order of multi-byte values, big-endian being the network order
+/// Modbus registers use.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+/// Where a measurement's value sits in a binary payload.
+#[derive(Debug, Clone)]
+pub struct BinaryField {
+    pub offset: usize,
+    pub value_type: BinaryType,
+    pub endianness: Endianness,
+    /// Multiplies the value, such as `0.1` for registers holding tenths of a degree
+    pub scale: Option<f64>,
+}
+
+impl BinaryField {
+    /// Reads the field from a payload as a JSON number.
+    pub fn read(&self, payload: &[u8]) -> Result<Value> {
+        let size = self.value_type.size();
+        let end = self.offset.checked_add(size);
+        let bytes = end.and_then(|end| payload.get(self.offset..end)).ok_or_else(|| {
+            let length = payload.len();
+            anyhow!("Failed to read {} bytes at offset {} of a {} byte binary payload", size, self.offset, length)
+        })?;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.endianness == Endianness::Big {
+            buffer[..size].reverse();
+        }
+        // the buffer now holds the value little-endian, zero padded
+        let unsigned = u64::from_le_bytes(buffer);
+        let value = match self.value_type {
+            BinaryType::U8 | BinaryType::U16 | BinaryType::U32 | BinaryType::U64 => Value::from(unsigned),
+            BinaryType::I8 => Value::from(unsigned as u8 as i8),
+            BinaryType::I16 => Value::from(unsigned as u16 as i16),
+            BinaryType::I32 => Value::from(unsigned as u32 as i32),
+            BinaryType::I64 => Value::from(unsigned as i64),
+            BinaryType::F32 => Value::from(f64::from(f32::from_bits(unsigned as u32))),
+            BinaryType::F64 => Value::from(f64::from_bits(unsigned)),
+        };
+        Ok(match self.scale {
+            Some(scale) => Value::from(value.as_f64().unwrap_or(f64::NAN) * scale),
+            None => value,
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::state::STATE;
+
+/// The range a reading of a measurement or field can physically take, and how
+/// far it can move between two readings. Readings beyond them are glitches of
+/// the sensor or the link, such as a temperature jumping by 400 °C.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bounds {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub max_step: Option<f64>,
+}
+
+/// The last accepted reading of a series, with a jump from it that the next
+/// reading may confirm.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Last {
+    value: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jump: Option<f64>,
+}
+
+impl Bounds {
+    pub fn new(min: Option<f64>, max: Option<f64>, max_step: Option<f64>, what: &str) -> Result<Self> {
+        if let (Some(min), Some(max)) = (min, max)
+            && min > max
+        {
+            return Err(anyhow!("The min of {} is above its max", what));
+        }
+        if max_step.is_some_and(|max_step| max_step.is_nan() || max_step < 0.0) {
+            return Err(anyhow!("The max_step of {} must not be negative", what));
+        }
+        Ok(Bounds { min, max, max_step })
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.min.is_some() || self.max.is_some() || self.max_step.is_some()
+    }
+
+    /// Whether `value` is a plausible reading of the series `key`, which is
+    /// remembered as the last one when it is. A jump beyond `max_step` is
+    /// rejected, but accepted once the next reading is within `max_step` of
+    /// it, so a real change of level is only delayed by one reading.
+    pub fn accepts(&self, key: Option<&str>, value: f64) -> bool {
+        if self.min.is_some_and(|min| value < min) || self.max.is_some_and(|max| value > max) {
+            return false;
+        }
+        let (Some(max_step), Some(key)) = (self.max_step, key) else {
+            return true;
+        };
+        STATE.update("bounds", key, |last| {
+            let previous: Option<Last> = last.clone().and_then(|last| serde_json::from_value(last).ok());
+            let accepted = previous.is_none_or(|previous| {
+                (value - previous.value).abs() <= max_step
+                    || previous.jump.is_some_and(|jump| (value - jump).abs() <= max_step)
+            });
+            let next = match previous {
+                Some(previous) if !accepted => Last { value: previous.value, jump: Some(value) },
+                _ => Last { value, jump: None },
+            };
+            *last = serde_json::to_value(next).ok();
+            accepted
+        })
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use chrono::Utc;
+use coap_lite::{CoapOption, CoapResponse, MessageClass, MessageType, Packet, RequestType, ResponseType};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+use crate::pipeline::Message;
+
+const DEFAULT_PORT: u16 = 5683;
+/// How often observations are registered again, re-establishing them after a
+/// device restarts and forgets its observers
+const OBSERVE_REFRESH: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CoapSourceConfig {
+    pub listen: String,
+    /// Resources on devices to observe, in addition to accepting requests
+    pub observe: Option<Vec<CoapObserveConfig>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CoapObserveConfig {
+    /// Resource to observe, e.g. `coap://192.168.1.20/sensors/env`
+    pub url: String,
+    /// Topic notifications are treated as published on, defaults to the resource path
+    pub topic: Option<String>,
+}
+
+struct Observation {
+    token: Vec<u8>,
+    address: SocketAddr,
+    path: String,
+    topic: String,
+}
+
+/// Splits a `coap://host[:port]/path` URL and resolves its host.
+async fn resolve(url: &str) -> Result<(SocketAddr, String)> {
+    let rest = url
+        .strip_prefix("coap://")
+        .ok_or_else(|| anyhow!("Unsupported CoAP URL {}, expected coap://host/path", url))?;
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let authority = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:{}", authority, DEFAULT_PORT)
+    };
+    let address = tokio::net::lookup_host(&authority)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("Could not resolve {}", authority))?;
+    Ok((address, path.to_string()))
+}
+
+/// Starts a CoAP endpoint feeding payloads into `messages` as if they had been
+/// published over MQTT. Devices can POST or PUT payloads to any path, which
+/// becomes the topic, and configured resources are observed for notifications.
+pub async fn spawn(config: &CoapSourceConfig, messages: mpsc::Sender<Message>) -> Result<()> {
+    let socket = Arc::new(
+        UdpSocket::bind(&config.listen)
+            .await
+            .map_err(|e| anyhow!("Failed to listen on {}: {}", config.listen, e))?,
+    );
+
+    let mut observations = Vec::new();
+    for (index, observe) in config.observe.iter().flatten().enumerate() {
+        let (address, path) = resolve(&observe.url).await?;
+        observations.push(Observation {
+            token: (index as u32).to_be_bytes().to_vec(),
+            address,
+            topic: observe.topic.clone().unwrap_or_else(|| path.clone()),
+            path,
+        });
+    }
+    let observations = Arc::new(observations);
+    let message_ids = Arc::new(AtomicU16::new(rand::random()));
+
+    info!("CoAP source listening on {}, observing {} resources", config.listen, observations.len());
+
+    if !observations.is_empty() {
+        let socket = socket.clone();
+        let observations = observations.clone();
+        let message_ids = message_ids.clone();
+        tokio::spawn(async move {
+            loop {
+                for observation in observations.iter() {
+                    let request = register(observation, message_ids.fetch_add(1, Ordering::Relaxed));
+                    if let Err(e) = send(&socket, &request, observation.address).await {
+                        warn!("Error observing coap://{}/{}: {}", observation.address, observation.path, e);
+                    }
+                }
+                tokio::time::sleep(OBSERVE_REFRESH).await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; 65536];
+        loop {
+            let (length, source) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Error receiving on the CoAP source: {}", e);
+                    continue;
+                }
+            };
+            let packet = match Packet::from_bytes(&buffer[..length]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    debug!("Ignoring invalid CoAP packet from {}: {}", source, e);
+                    continue;
+                }
+            };
+
+            let (reply, message) = match packet.header.code {
+                MessageClass::Request(method) => {
+                    let Some(mut response) = CoapResponse::new(&packet) else {
+                        continue;
+                    };
+                    let message = matches!(method, RequestType::Post | RequestType::Put).then(|| Message {
+                        topic: uri_path(&packet),
+                        payload: Bytes::from(packet.payload.clone()),
+                        qos: 0,
+                        retain: false,
+                        received_at: Utc::now(),
+                    });
+                    response.set_status(if message.is_some() {
+                        ResponseType::Changed
+                    } else {
+                        ResponseType::MethodNotAllowed
+                    });
+                    (Some(response.message), message)
+                }
+                MessageClass::Response(status) => {
+                    let observation = observations.iter().find(|o| o.token == packet.get_token());
+                    let reply = match (observation, packet.header.get_type()) {
+                        // stop notifications for observations we no longer know about
+                        (None, MessageType::Confirmable | MessageType::NonConfirmable) => Some(empty(&packet, MessageType::Reset)),
+                        (Some(_), MessageType::Confirmable) => Some(empty(&packet, MessageType::Acknowledgement)),
+                        _ => None,
+                    };
+                    let message = observation.and_then(|observation| {
+                        if status.is_error() {
+                            warn!("Observing coap://{}/{} failed: {}", observation.address, observation.path, packet.header.get_code());
+                            return None;
+                        }
+                        (!packet.payload.is_empty()).then(|| Message {
+                            topic: observation.topic.clone(),
+                            payload: Bytes::from(packet.payload.clone()),
+                            qos: 0,
+                            retain: false,
+                            received_at: Utc::now(),
+                        })
+                    });
+                    (reply, message)
+                }
+                _ => continue,
+            };
+
+            if let Some(reply) = reply
+                && let Err(e) = send(&socket, &reply, source).await
+            {
+                debug!("Error replying to {}: {}", source, e);
+            }
+            if let Some(message) = message
+                && messages.send(message).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+fn uri_path(packet: &Packet) -> String {
+    packet
+        .get_option(CoapOption::UriPath)
+        .map(|segments| {
+            segments
+                .iter()
+                .map(|segment| String::from_utf8_lossy(segment))
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .unwrap_or_default()
+}
+
+/// A GET request registering the bridge as an observer of a resource.
+fn register(observation: &Observation, message_id: u16) -> Packet {
+    let mut packet = Packet::new();
+    packet.header.set_type(MessageType::NonConfirmable);
+    packet.header.code = MessageClass::Request(RequestType::Get);
+    packet.header.message_id = message_id;
+    packet.set_token(observation.token.clone());
+    packet.set_observe_value(0);
+    for segment in observation.path.split('/').filter(|segment| !segment.is_empty()) {
+        packet.add_option(CoapOption::UriPath, segment.as_bytes().to_vec());
+    }
+    packet
+}
+
+/// An empty acknowledgement or reset for a received message.
+fn empty(packet: &Packet, kind: MessageType) -> Packet {
+    let mut reply = Packet::new();
+    reply.header.set_type(kind);
+    reply.header.code = MessageClass::Empty;
+    reply.header.message_id = packet.header.message_id;
+    reply
+}
+
+async fn send(socket: &UdpSocket, packet: &Packet, address: SocketAddr) -> Result<()> {
+    let bytes = packet.to_bytes().map_err(|e| anyhow!("Failed to encode CoAP packet: {}", e))?;
+    socket.send_to(&bytes, address).await?;
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use anyhow::{anyhow, Result};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::io::Read;
+
+/// Payloads larger than this once decompressed are rejected, so a small
+/// malicious payload cannot exhaust memory.
+const MAX_DECOMPRESSED: u64 = 64 * 1024 * 1024;
+
+/// How message payloads are compressed.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Payloads are used as they are
+    #[default]
+    None,
+    /// Payloads starting with a gzip or zlib header are decompressed, others
+    /// are used as they are. A zlib header is only two bytes, which text such
+    /// as `80` also starts with, so a payload that fails to decompress as zlib
+    /// is used as it is
+    Auto,
+    Gzip,
+    /// A zlib stream, or raw deflate data without the zlib header
+    Deflate,
+}
+
+/// Decompresses a payload, borrowing it unchanged when it isn't compressed.
+pub fn decompress(compression: Compression, payload: &[u8]) -> Result<Cow<'_, [u8]>> {
+    match compression {
+        Compression::None => Ok(Cow::Borrowed(payload)),
+        Compression::Auto if is_gzip(payload) => read("gzip", GzDecoder::new(payload)),
+        Compression::Auto if is_zlib(payload) => {
+            Ok(read("deflate", ZlibDecoder::new(payload)).unwrap_or(Cow::Borrowed(payload)))
+        }
+        Compression::Auto => Ok(Cow::Borrowed(payload)),
+        Compression::Gzip => read("gzip", GzDecoder::new(payload)),
+        Compression::Deflate if is_zlib(payload) => read("deflate", ZlibDecoder::new(payload)),
+        Compression::Deflate => read("deflate", DeflateDecoder::new(payload)),
+    }
+}
+
+fn is_gzip(payload: &[u8]) -> bool {
+    payload.starts_with(&[0x1f, 0x8b])
+}
+
+/// A zlib header is a deflate method byte with a window of at most 32 KiB and
+/// a flag byte making the pair a multiple of 31.
+fn is_zlib(payload: &[u8]) -> bool {
+    match payload {
+        [method, flags, ..] => {
+            method & 0x0f == 8 && method >> 4 <= 7 && (u16::from(*method) << 8 | u16::from(*flags)) % 31 == 0
+        }
+        _ => false,
+    }
+}
+
+fn read(name: &str, decoder: impl Read) -> Result<Cow<'static, [u8]>> {
+    let mut decompressed = Vec::new();
+    decoder
+        .take(MAX_DECOMPRESSED + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| anyhow!("Failed to decompress {} payload: {}", name, e))?;
+    if decompressed.len() as u64 > MAX_DECOMPRESSED {
+        return Err(anyhow!("Failed to decompress {} payload: larger than {} bytes", name, MAX_DECOMPRESSED));
+    }
+    Ok(Cow::Owned(decompressed))
+}
@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use evalexpr::{ContextWithMutableFunctions, EvalexprError, EvalexprResult, Function, HashMapContext, Value};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::state::STATE;
+
+/// The expression functions comparing a reading with the previous one.
+pub const FUNCTIONS: [&str; 2] = ["delta", "rate"];
+
+/// The last reading of a series, kept in the pipeline state so a restart
+/// carries on from it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Reading {
+    value: f64,
+    timestamp: DateTime<Utc>,
+}
+
+/// `delta` and `rate` for one evaluation of an expression on a reading of a
+/// series. `delta(value)` is the increase since the previous reading and
+/// `rate(value)` that increase per second, which turns a cumulative counter
+/// such as an energy meter into the consumption of each interval.
+pub struct Counter {
+    key: String,
+    timestamp: DateTime<Utc>,
+    previous: Option<Reading>,
+    /// What the functions were given, saved as the next previous reading
+    latest: Arc<Mutex<Option<f64>>>,
+}
+
+impl Counter {
+    pub fn new(key: String, timestamp: DateTime<Utc>) -> Self {
+        let previous = STATE.update("counters", &key, |reading| {
+            reading.clone().and_then(|reading| serde_json::from_value(reading).ok())
+        });
+        Counter { key, timestamp, previous, latest: Arc::new(Mutex::new(None)) }
+    }
+
+    /// Adds the functions to the context an expression is evaluated in.
+    pub fn install(&self, context: &mut HashMapContext) -> EvalexprResult<()> {
+        for (name, per_second) in [("delta", false), ("rate", true)] {
+            let (previous, timestamp, latest) = (self.previous, self.timestamp, self.latest.clone());
+            let function = Function::new(move |argument| {
+                let (value, max) = arguments(argument)?;
+                *latest.lock().unwrap() = Some(value);
+                // nothing to compare the first reading with, nor a redelivered one
+                let Some(previous) = previous.filter(|previous| previous.timestamp < timestamp) else {
+                    return Ok(Value::Empty);
+                };
+                let delta = match (value - previous.value, max) {
+                    (delta, _) if delta >= 0.0 => delta,
+                    // a counter wrapping around to 0 at `max`
+                    (_, Some(max)) => value + max - previous.value,
+                    // a counter reset to 0, as when the device restarts
+                    (_, None) => value,
+                };
+                if !per_second {
+                    return Ok(Value::Float(delta));
+                }
+                let seconds = (timestamp - previous.timestamp).as_seconds_f64();
+                Ok(Value::Float(delta / seconds))
+            });
+            context.set_function(name.to_string(), function)?;
+        }
+        Ok(())
+    }
+
+    /// Remembers the reading the functions were given, unless it is no newer
+    /// than the previous one.
+    pub fn save(self) {
+        let Some(value) = *self.latest.lock().unwrap() else {
+            return;
+        };
+        if self.previous.is_some_and(|previous| previous.timestamp >= self.timestamp) {
+            return;
+        }
+        let reading = Reading { value, timestamp: self.timestamp };
+        STATE.update("counters", &self.key, |last| *last = serde_json::to_value(reading).ok());
+    }
+}
+
+/// The reading and the optional value the counter wraps around at.
+fn arguments(argument: &Value) -> EvalexprResult<(f64, Option<f64>)> {
+    match argument {
+        Value::Tuple(arguments) => match arguments.as_slice() {
+            [value, max] => Ok((value.as_number()?, Some(max.as_number()?))),
+            _ => Err(EvalexprError::wrong_function_argument_amount_range(arguments.len(), 1..=2)),
+        },
+        value => Ok((value.as_number()?, None)),
+    }
+}
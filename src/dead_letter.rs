@@ -0,0 +1,44 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::influx::line_protocol;
+use crate::pipeline::Point;
+
+/// Appends points that could not be written to a file, as line protocol that
+/// `influx write` can import once InfluxDB is reachable again.
+///
+/// Each point is preceded by a `#` comment with the time, the bucket of points
+/// written to their own and the error, which line protocol parsers ignore.
+pub struct DeadLetter {
+    path: String,
+    file: Mutex<File>,
+}
+
+impl DeadLetter {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open dead letter file {}: {}", path, e))?;
+        Ok(DeadLetter {
+            path: path.to_string(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn write(&self, point: &Point, error: &anyhow::Error) -> Result<()> {
+        // comments end at the line break, so the error has to stay on one line
+        let error = error.to_string().replace(['\n', '\r'], " ");
+        let bucket = point.bucket.as_ref().map(|bucket| format!(" bucket {}:", bucket)).unwrap_or_default();
+        let entry = format!("# {}{} {}\n{}\n", Utc::now().to_rfc3339(), bucket, error, line_protocol(point));
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(entry.as_bytes())
+            .map_err(|e| anyhow!("Failed to write to dead letter file {}: {}", self.path, e))
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use prost_reflect::MessageDescriptor;
+use serde_json::Value;
+use std::borrow::Cow;
+
+use crate::binary::BinaryField;
+use crate::delimited;
+use crate::protobuf;
+use crate::xml;
+
+/// Turns a message payload into the JSON document measurements are extracted
+/// from. Every payload format is a decoder of its own, so one can be added
+/// without touching the others.
+pub trait Decoder: Send + Sync {
+    fn decode(&self, payload: &[u8]) -> Result<Value>;
+}
+
+/// Reads the payload as text, replacing invalid UTF-8 when `lossy_utf8` is set.
+fn text(payload: &[u8], lossy_utf8: bool) -> Result<Cow<'_, str>> {
+    // invalid bytes become U+FFFD instead of failing the whole message
+    if lossy_utf8 {
+        Ok(String::from_utf8_lossy(payload))
+    } else {
+        Ok(Cow::Borrowed(std::str::from_utf8(payload)?))
+    }
+}
+
+pub struct Json {
+    pub lossy_utf8: bool,
+    #[cfg(feature = "simd-json")]
+    pub simd_json: bool,
+}
+
+impl Decoder for Json {
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        let payload = text(payload, self.lossy_utf8)?;
+        #[cfg(feature = "simd-json")]
+        if self.simd_json {
+            // simd-json parses in place, so it needs its own mutable copy of the payload
+            let mut buffer = payload.as_bytes().to_vec();
+            return Ok(simd_json::serde::from_slice(&mut buffer)?);
+        }
+        Ok(serde_json::from_str(&payload)?)
+    }
+}
+
+/// Reads a bare payload such as `23.4` as a number, or anything else as text.
+pub struct Raw {
+    pub lossy_utf8: bool,
+}
+
+impl Decoder for Raw {
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        let text = text(payload, self.lossy_utf8)?;
+        let text = text.trim();
+        if let Ok(integer) = text.parse::<i64>() {
+            return Ok(integer.into());
+        }
+        Ok(text
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or_else(|| Value::String(text.to_string()), Value::Number))
+    }
+}
+
+pub struct MessagePack;
+
+impl Decoder for MessagePack {
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        rmp_serde::from_slice(payload).map_err(|e| anyhow!("Failed to decode MessagePack payload: {}", e))
+    }
+}
+
+pub struct Cbor;
+
+impl Decoder for Cbor {
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        ciborium::from_reader(payload).map_err(|e| anyhow!("Failed to decode CBOR payload: {}", e))
+    }
+}
+
+pub struct Xml {
+    pub lossy_utf8: bool,
+}
+
+impl Decoder for Xml {
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        xml::to_json(&text(payload, self.lossy_utf8)?)
+    }
+}
+
+pub struct Csv {
+    pub lossy_utf8: bool,
+    pub delimiter: u8,
+    /// Whether payloads start with a line of column names
+    pub header: bool,
+}
+
+impl Decoder for Csv {
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        delimited::to_json(&text(payload, self.lossy_utf8)?, self.delimiter, self.header)
+    }
+}
+
+/// Decodes protobuf messages of one type.
+pub struct Protobuf(pub MessageDescriptor);
+
+impl Decoder for Protobuf {
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        protobuf::decode(&self.0, payload)
+    }
+}
+
+/// Reads the one value a measurement's binary field holds.
+pub struct Binary(pub BinaryField);
+
+impl Decoder for Binary {
+    fn decode(&self, payload: &[u8]) -> Result<Value> {
+        self.0.read(payload)
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Stable FNV-1a hash of a message, so keys survive restarts and upgrades.
+pub fn message_key(topic: &str, payload: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in topic.as_bytes().iter().chain([0u8].iter()).chain(payload) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Suppresses redeliveries of messages already seen within a short window,
+/// such as QoS 1 messages the broker sends again after a reconnect.
+pub struct Deduplicator {
+    window: Duration,
+    seen: HashMap<u64, Instant>,
+    order: VecDeque<(Instant, u64)>,
+}
+
+impl Deduplicator {
+    pub fn new(window: Duration) -> Self {
+        Deduplicator {
+            window,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns true when the message is marked as a redelivery (`dup`) and the
+    /// same topic and payload were seen within the window. A device repeating
+    /// a reading sends it afresh, without the flag, so it is never suppressed.
+    pub fn is_duplicate(&mut self, topic: &str, payload: &[u8], dup: bool) -> bool {
+        let now = Instant::now();
+        while let Some((seen_at, key)) = self.order.front().copied() {
+            if now.duration_since(seen_at) < self.window {
+                break;
+            }
+            self.order.pop_front();
+            // the key may have been seen again since, only forget the latest sighting
+            if self.seen.get(&key) == Some(&seen_at) {
+                self.seen.remove(&key);
+            }
+        }
+
+        let key = message_key(topic, payload);
+        if dup && self.seen.contains_key(&key) {
+            return true;
+        }
+        self.seen.insert(key, now);
+        self.order.push_back((now, key));
+        false
+    }
+}
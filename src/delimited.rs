@@ -0,0 +1,61 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// A column of a delimited payload, by position counting from zero or, when
+/// the payload has a header line, by name.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl Column {
+    /// The JSONPath of this column in the first row.
+    pub fn path(&self) -> String {
+        match self {
+            Column::Index(index) => format!("$[0][{}]", index),
+            Column::Name(name) => format!("$[0]['{}']", name.replace('\'', "\\'")),
+        }
+    }
+}
+
+/// Converts delimited lines such as `1699999999,23.4,56.1` to a JSON array with
+/// one entry per line: an array of the values, or with a header line an object
+/// keyed by the column names. Numeric values become numbers.
+pub fn to_json(text: &str, delimiter: u8, header: bool) -> Result<Value> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(header)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+    let names = if header {
+        reader.headers().map_err(|e| anyhow!("Failed to read CSV header: {}", e))?.clone()
+    } else {
+        csv::StringRecord::new()
+    };
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| anyhow!("Failed to parse CSV payload: {}", e))?;
+        let values = record.iter().map(value);
+        rows.push(if header {
+            Value::Object(names.iter().map(str::to_string).zip(values).collect::<Map<_, _>>())
+        } else {
+            Value::Array(values.collect())
+        });
+    }
+    Ok(Value::Array(rows))
+}
+
+fn value(field: &str) -> Value {
+    if let Ok(integer) = field.parse::<i64>() {
+        return integer.into();
+    }
+    field
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map_or_else(|| Value::String(field.to_string()), Value::Number)
+}
@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+
+use crate::metrics::METRICS;
+use crate::pipeline::Point;
+
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DiskBufferConfig {
+    /// File the points are appended to
+    pub path: String,
+    pub max_bytes: Option<u64>,
+    pub max_age_secs: Option<u64>,
+}
+
+struct State {
+    file: File,
+    /// Where the points that have not been replayed yet start
+    offset: u64,
+    len: u64,
+    /// How far expired points have been counted as dropped, so points read
+    /// again after a failed replay are not counted twice
+    expired: u64,
+    /// Bytes of replayed points removed from the start of the file, which the
+    /// offsets handed out by `peek` include
+    compacted: u64,
+}
+
+/// Points captured while InfluxDB was unreachable, kept in an append-only file
+/// of JSON lines until they are replayed in the order they were buffered.
+///
+/// The file is emptied once everything has been replayed. After a crash part
+/// of it may be replayed again, which InfluxDB overwrites as the points keep
+/// their timestamps.
+pub struct DiskBuffer {
+    path: String,
+    state: Mutex<State>,
+    max_bytes: u64,
+    max_age: Option<chrono::Duration>,
+}
+
+impl DiskBuffer {
+    pub fn open(config: &DiskBufferConfig) -> Result<Self> {
+        let path = &config.path;
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open disk buffer {}: {}", path, e))?;
+        let len = file.metadata().map_err(|e| anyhow!("Failed to read disk buffer {}: {}", path, e))?.len();
+        if len > 0 {
+            info!("Disk buffer {} holds {} bytes of points to replay", path, len);
+        }
+        METRICS.disk_buffer_bytes.store(len, Ordering::Relaxed);
+        let buffer = DiskBuffer {
+            path: path.clone(),
+            state: Mutex::new(State { file, offset: 0, len, expired: 0, compacted: 0 }),
+            max_bytes: config.max_bytes.unwrap_or(DEFAULT_MAX_BYTES),
+            max_age: config.max_age_secs.map(|secs| chrono::Duration::seconds(secs as i64)),
+        };
+        buffer.track_oldest(&buffer.state.lock().unwrap())?;
+        Ok(buffer)
+    }
+
+    /// Reports the timestamp of the first point waiting to be replayed, for
+    /// the age of the oldest buffered point. Expired points are left out, as
+    /// they are discarded rather than replayed.
+    fn track_oldest(&self, state: &State) -> Result<()> {
+        let mut file = &state.file;
+        file.seek(SeekFrom::Start(state.offset))
+            .map_err(|e| anyhow!("Failed to read disk buffer {}: {}", self.path, e))?;
+        let reader = BufReader::new(file.take(state.len - state.offset));
+        let expiry = self.max_age.map(|max_age| Utc::now() - max_age);
+        let mut oldest = None;
+        for line in reader.lines() {
+            let line = line.map_err(|e| anyhow!("Failed to read disk buffer {}: {}", self.path, e))?;
+            // a line cut short by a crash is skipped when replaying as well
+            if let Ok(point) = serde_json::from_str::<Point>(&line)
+                && expiry.is_none_or(|expiry| point.timestamp >= expiry)
+            {
+                oldest = Some(point.timestamp);
+                break;
+            }
+        }
+        store_oldest(oldest);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.offset == state.len
+    }
+
+    /// Appends points to the end of the buffer, failing once the points waiting
+    /// to be replayed would grow beyond `max_bytes`.
+    pub fn push(&self, points: &[Point]) -> Result<()> {
+        let mut entries = String::new();
+        for point in points {
+            entries.push_str(&serde_json::to_string(point)?);
+            entries.push('\n');
+        }
+        let mut state = self.state.lock().unwrap();
+        let was_empty = state.offset == state.len;
+        if state.len + entries.len() as u64 > self.max_bytes && state.offset > 0 {
+            self.compact(&mut state)?;
+        }
+        if state.len + entries.len() as u64 > self.max_bytes {
+            return Err(anyhow!("Disk buffer {} is full ({} bytes)", self.path, self.max_bytes));
+        }
+        state
+            .file
+            .write_all(entries.as_bytes())
+            .map_err(|e| anyhow!("Failed to write to disk buffer {}: {}", self.path, e))?;
+        state.len += entries.len() as u64;
+        METRICS.disk_buffer_bytes.store(state.len - state.offset, Ordering::Relaxed);
+        if was_empty {
+            store_oldest(points.first().map(|point| point.timestamp));
+        }
+        Ok(())
+    }
+
+    /// Reads up to `count` of the oldest points, returning them with the offset
+    /// to pass to `consume` once they are written. Points older than
+    /// `max_age_secs` are discarded and counted as dropped.
+    pub fn peek(&self, count: usize) -> Result<(Vec<Point>, u64)> {
+        let mut state = self.state.lock().unwrap();
+        let mut file = &state.file;
+        file.seek(SeekFrom::Start(state.offset))
+            .map_err(|e| anyhow!("Failed to read disk buffer {}: {}", self.path, e))?;
+        let mut reader = BufReader::new(file.take(state.len - state.offset));
+        let oldest = self.max_age.map(|max_age| Utc::now() - max_age);
+        let mut points = Vec::new();
+        let mut expired = 0;
+        let mut offset = state.offset;
+        let mut counted = state.expired;
+        let mut line = String::new();
+        while points.len() < count {
+            line.clear();
+            let read = reader
+                .read_line(&mut line)
+                .map_err(|e| anyhow!("Failed to read disk buffer {}: {}", self.path, e))?;
+            if read == 0 {
+                break;
+            }
+            let start = offset;
+            offset += read as u64;
+            match serde_json::from_str::<Point>(line.trim_end()) {
+                Ok(point) if oldest.is_some_and(|oldest| point.timestamp < oldest) => {
+                    if start >= counted {
+                        expired += 1;
+                        counted = offset;
+                    }
+                }
+                Ok(point) => points.push(point),
+                // a line cut short by a crash while appending
+                Err(e) => warn!("Skipping unreadable entry in disk buffer {}: {}", self.path, e),
+            }
+        }
+        drop(reader);
+        state.expired = counted;
+        if expired > 0 {
+            METRICS.dropped_points.fetch_add(expired, Ordering::Relaxed);
+            warn!("Discarded {} points older than max_age_secs from disk buffer {}", expired, self.path);
+        }
+        Ok((points, offset + state.compacted))
+    }
+
+    /// Marks the points before `offset` as replayed, emptying the file once
+    /// nothing is left.
+    pub fn consume(&self, offset: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.offset = offset - state.compacted;
+        if state.offset == state.len {
+            state
+                .file
+                .set_len(0)
+                .map_err(|e| anyhow!("Failed to truncate disk buffer {}: {}", self.path, e))?;
+            state.offset = 0;
+            state.len = 0;
+            state.expired = 0;
+        }
+        METRICS.disk_buffer_bytes.store(state.len - state.offset, Ordering::Relaxed);
+        self.track_oldest(&state)
+    }
+
+    /// Removes the replayed points from the start of the file, which a long
+    /// replay that new points keep arriving during never empties.
+    fn compact(&self, state: &mut State) -> Result<()> {
+        let temporary = format!("{}.tmp", self.path);
+        let mut file = &state.file;
+        file.seek(SeekFrom::Start(state.offset))
+            .map_err(|e| anyhow!("Failed to read disk buffer {}: {}", self.path, e))?;
+        let mut copy =
+            File::create(&temporary).map_err(|e| anyhow!("Failed to create disk buffer {}: {}", temporary, e))?;
+        std::io::copy(&mut file.take(state.len - state.offset), &mut copy)
+            .and_then(|_| copy.sync_all())
+            .map_err(|e| anyhow!("Failed to write disk buffer {}: {}", temporary, e))?;
+        fs::rename(&temporary, &self.path)
+            .map_err(|e| anyhow!("Failed to replace disk buffer {}: {}", self.path, e))?;
+        state.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| anyhow!("Failed to open disk buffer {}: {}", self.path, e))?;
+        state.len -= state.offset;
+        state.expired = state.expired.saturating_sub(state.offset);
+        state.compacted += state.offset;
+        state.offset = 0;
+        Ok(())
+    }
+}
+
+fn store_oldest(timestamp: Option<DateTime<Utc>>) {
+    let millis = timestamp.map_or(0, |timestamp| timestamp.timestamp_millis());
+    METRICS.disk_buffer_oldest_ms.store(millis, Ordering::Relaxed);
+}
@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use rumqttc::{AsyncClient, Event, LastWill, Packet, Publish, QoS};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::dedup::message_key;
+use crate::failures::FAILURES;
+use crate::influx::InfluxClient;
+use crate::pipeline::{Message, Pipeline, combine_errors};
+use crate::mqtt::{self, Backoff};
+use crate::retained::RetainedFilter;
+use crate::sink::Sink;
+use crate::topic_filter;
+use crate::{Config, mqtt_options, subscriptions, topic_list};
+
+const DEFAULT_JOURNAL_ENTRIES: usize = 10000;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExactlyOnceConfig {
+    /// File recording the timestamp assigned to each recently processed message
+    pub journal: String,
+    pub journal_entries: Option<usize>,
+}
+
+/// Remembers which timestamp each recent message was written with.
+///
+/// A message the broker redelivers after a crash is written again with its
+/// original timestamp, so InfluxDB overwrites the existing point instead of
+/// storing a duplicate.
+struct Journal {
+    path: String,
+    file: File,
+    entries: HashMap<u64, DateTime<Utc>>,
+    order: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl Journal {
+    fn open(config: &ExactlyOnceConfig) -> Result<Self> {
+        let capacity = config.journal_entries.unwrap_or(DEFAULT_JOURNAL_ENTRIES).max(1);
+        let mut entries = HashMap::new();
+        let mut order = VecDeque::new();
+        if let Ok(file) = File::open(&config.journal) {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                let Some((key, nanos)) = line.split_once(' ') else {
+                    continue;
+                };
+                let (Ok(key), Ok(nanos)) = (u64::from_str_radix(key, 16), nanos.parse::<i64>()) else {
+                    continue;
+                };
+                if entries.insert(key, DateTime::from_timestamp_nanos(nanos)).is_none() {
+                    order.push_back(key);
+                }
+            }
+        }
+        while order.len() > capacity {
+            if let Some(key) = order.pop_front() {
+                entries.remove(&key);
+            }
+        }
+
+        let mut journal = Journal {
+            path: config.journal.clone(),
+            file: OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&config.journal)
+                .map_err(|e| anyhow!("Failed to open journal {}: {}", config.journal, e))?,
+            entries,
+            order,
+            capacity,
+        };
+        journal.compact()?;
+        Ok(journal)
+    }
+
+    /// Rewrites the journal with only the retained entries.
+    fn compact(&mut self) -> Result<()> {
+        let mut file = File::create(&self.path)?;
+        for key in &self.order {
+            writeln!(file, "{:016x} {}", key, self.entries[key].timestamp_nanos_opt().unwrap_or_default())?;
+        }
+        file.sync_all()?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    /// Returns the timestamp to write a message with, durably recording it
+    /// before anything is written to InfluxDB.
+    fn timestamp_for(&mut self, publish: &Publish, received_at: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        let key = message_key(&publish.topic, &publish.payload);
+        // only redeliveries may reuse a timestamp, a legitimately repeated
+        // payload is a new reading and gets a new point
+        if publish.dup
+            && let Some(timestamp) = self.entries.get(&key)
+        {
+            debug!("Redelivered message on {}, reusing timestamp {}", publish.topic, timestamp);
+            return Ok(*timestamp);
+        }
+
+        let timestamp = received_at;
+        writeln!(self.file, "{:016x} {}", key, timestamp.timestamp_nanos_opt().unwrap_or_default())?;
+        self.file.sync_data()?;
+        if self.entries.insert(key, timestamp).is_none() {
+            self.order.push_back(key);
+        }
+        if self.order.len() > self.capacity * 2 {
+            while self.order.len() > self.capacity {
+                if let Some(key) = self.order.pop_front() {
+                    self.entries.remove(&key);
+                }
+            }
+            self.compact()?;
+        }
+        Ok(timestamp)
+    }
+}
+
+/// Writes every point of a message, retrying until InfluxDB accepts them all.
+/// Points InfluxDB rejects as invalid would never be accepted, so they are
+/// reported as errors instead.
+async fn write_until_success(pipeline: &Pipeline, message: &Message, influx_client: &InfluxClient, bucket: &str) -> Result<()> {
+    let extraction = pipeline.extract(message)?;
+    let mut errors = extraction.errors;
+    // points routed to other sinks only are not written, as those sinks are not used in this mode
+    let routed = extraction.points.iter().filter(|point| {
+        point.sinks.as_ref().is_none_or(|sinks| sinks.iter().any(|sink| sink == "influxdb"))
+    });
+    for point in routed {
+        let mut backoff = Backoff::with_limits(RETRY_DELAY, MAX_RETRY_DELAY, true);
+        // each retry resends the same point, timestamp included, so a write that
+        // actually landed before failing is overwritten rather than duplicated
+        while let Err(e) = influx_client.write(point, point.bucket.as_deref().unwrap_or(bucket)).await {
+            if !influx_client.is_retryable(&e) {
+                errors.push(anyhow!("Error writing measurement {}: {}", point.measurement, e));
+                break;
+            }
+            let delay = backoff.next_delay();
+            warn!("Error writing measurement {}, retrying in {} ms: {}", point.measurement, delay.as_millis(), e);
+            tokio::time::sleep(delay).await;
+        }
+    }
+    combine_errors(errors)
+}
+
+/// Runs the bridge with QoS 2 subscriptions, acknowledging each message only
+/// once its points are safely in InfluxDB.
+pub async fn run_exactly_once(config: &Config, exactly_once: &ExactlyOnceConfig) -> Result<()> {
+    let Some(influxdb) = &config.influxdb else {
+        return Err(anyhow!("Exactly-once mode requires an [influxdb] section"));
+    };
+    let pipeline = Pipeline::new(config)?;
+    let influx_client = InfluxClient::new(influxdb)?;
+    let mut journal = Journal::open(exactly_once)?;
+
+    // a persistent session makes the broker keep unacknowledged messages while we are down
+    let mut mqttoptions = mqtt_options(config, &mqtt::client_id(config))?;
+    mqttoptions.set_manual_acks(true);
+    mqttoptions.set_clean_session(false);
+    let status = mqtt::status(config);
+    if let Some(status) = &status {
+        mqttoptions.set_last_will(LastWill::new(&status.topic, status.offline.as_str(), QoS::AtLeastOnce, true));
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+    let topics = subscriptions(config)?;
+    if topics.iter().any(|(topic, _)| topic_filter::shared(topic).is_some()) {
+        // the journal is local, so a message redelivered to another member is written with a new timestamp
+        warn!("Shared subscriptions in exactly-once mode can duplicate points when another bridge receives a redelivery");
+    }
+    let mut backoff = Backoff::new(config);
+    let mut subscribed = false;
+
+    // messages are processed and acknowledged strictly in arrival order
+    let (tx, mut rx) = mpsc::channel::<(Publish, DateTime<Utc>)>(100);
+    let ack_client = client.clone();
+    let bucket = influxdb.bucket.clone();
+    let terminate_on_error = config.terminate_on_error.unwrap_or(false);
+    let retained = RetainedFilter::new(config);
+    let processor = tokio::spawn(async move {
+        while let Some((publish, received_at)) = rx.recv().await {
+            if !retained.accept(&publish) {
+                debug!("Skipping retained message on {}", publish.topic);
+                ack_client.ack(&publish).await?;
+                continue;
+            }
+            let mut message = Message::from(publish.clone());
+            message.received_at = journal.timestamp_for(&publish, received_at)?;
+            if let Err(e) = write_until_success(&pipeline, &message, &influx_client, &bucket).await {
+                // the payload itself is unusable, acknowledge it so it is not redelivered forever
+                error!("Error processing message on {}: {}", message.topic, e);
+                FAILURES.record(Some(&message.topic), &message.payload, &e);
+                if terminate_on_error {
+                    return Err(e);
+                }
+            }
+            ack_client.ack(&publish).await?;
+        }
+        Ok(())
+    });
+
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                if tx.send((publish, Utc::now())).await.is_err() {
+                    return match processor.await {
+                        Ok(Err(e)) => Err(e),
+                        _ => Err(anyhow!("Message processor stopped")),
+                    };
+                }
+            }
+            Ok(Event::Incoming(Packet::ConnAck(connack))) => {
+                backoff.reset();
+                if let Some(status) = &status {
+                    let client = client.clone();
+                    let (topic, online) = (status.topic.clone(), status.online.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = client.publish(&topic, QoS::AtLeastOnce, true, online).await {
+                            error!("Failed to publish to {}: {}", topic, e);
+                        }
+                    });
+                }
+                if connack.session_present && subscribed {
+                    info!("Reconnected to MQTT, resuming the session");
+                } else {
+                    // subscribing from a separate task, as the requests only leave while the event loop is polled
+                    let client = client.clone();
+                    let pending = topics.clone();
+                    tokio::spawn(async move {
+                        for (topic, _) in pending {
+                            if let Err(e) = client.subscribe(&topic, QoS::ExactlyOnce).await {
+                                error!("Failed to subscribe to {}: {}", topic, e);
+                            }
+                        }
+                    });
+                    subscribed = true;
+                    info!("Connected to MQTT and subscribed to {} with exactly-once delivery", topic_list(&topics));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error in event loop: {}", e);
+                if terminate_on_error {
+                    return Err(e.into());
+                }
+                tokio::time::sleep(backoff.next_delay()).await;
+            }
+        }
+    }
+}
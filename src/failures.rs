@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const DEFAULT_HISTORY: usize = 100;
+/// Longest payload excerpt kept per failure
+const SNIPPET_BYTES: usize = 256;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub at: DateTime<Utc>,
+    /// The topic the message arrived on, when the failure can be traced to one
+    pub topic: Option<String>,
+    /// The start of the payload, or the line protocol of a point that failed to write
+    pub snippet: String,
+    pub error: String,
+}
+
+/// The most recent failures, served on the admin `/failures` endpoint so a
+/// missing sensor can be diagnosed without turning on debug logging first.
+pub struct Failures {
+    history: AtomicUsize,
+    recent: Mutex<VecDeque<Failure>>,
+}
+
+pub static FAILURES: Failures = Failures {
+    history: AtomicUsize::new(DEFAULT_HISTORY),
+    recent: Mutex::new(VecDeque::new()),
+};
+
+impl Failures {
+    /// Sets how many failures are kept, 0 disables recording.
+    pub fn set_history(&self, history: usize) {
+        self.history.store(history, Ordering::Relaxed);
+        let mut recent = self.recent.lock().unwrap();
+        while recent.len() > history {
+            recent.pop_front();
+        }
+    }
+
+    pub fn record(&self, topic: Option<&str>, payload: &[u8], error: &anyhow::Error) {
+        let history = self.history.load(Ordering::Relaxed);
+        if history == 0 {
+            return;
+        }
+        let failure = Failure {
+            at: Utc::now(),
+            topic: topic.map(str::to_string),
+            snippet: String::from_utf8_lossy(&payload[..payload.len().min(SNIPPET_BYTES)]).into_owned(),
+            error: error.to_string(),
+        };
+        let mut recent = self.recent.lock().unwrap();
+        while recent.len() >= history {
+            recent.pop_front();
+        }
+        recent.push_back(failure);
+    }
+
+    /// The recorded failures, newest first.
+    pub fn recent(&self) -> Vec<Failure> {
+        self.recent.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
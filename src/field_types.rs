@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// What happens when a measurement's value arrives with a different JSON type
+/// than the first value seen for it.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldTypeMismatch {
+    /// Ignore the change and read the value like any other, so strings are
+    /// parsed as numbers. `cast` is accepted as well, the name it had before.
+    #[default]
+    #[serde(alias = "cast")]
+    Accept,
+    /// Skip the value and report it as an error
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Number,
+    String,
+}
+
+impl ValueKind {
+    pub fn of(value: &serde_json::Value) -> Option<Self> {
+        match value {
+            serde_json::Value::Number(_) => Some(ValueKind::Number),
+            serde_json::Value::String(_) => Some(ValueKind::String),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueKind::Number => write!(f, "number"),
+            ValueKind::String => write!(f, "string"),
+        }
+    }
+}
+
+/// Remembers the first-seen value type of every measurement, so devices that
+/// alternate between numbers and strings are caught instead of silently
+/// coerced.
+pub struct FieldTypes {
+    mismatch: FieldTypeMismatch,
+    seen: Mutex<HashMap<String, ValueKind>>,
+}
+
+impl FieldTypes {
+    pub fn new(mismatch: FieldTypeMismatch) -> Self {
+        FieldTypes {
+            mismatch,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the type of a value for `measurement`, failing when it differs
+    /// from the first one seen and mismatches are rejected.
+    pub fn check(&self, measurement: &str, value: &serde_json::Value) -> Result<()> {
+        if self.mismatch == FieldTypeMismatch::Accept {
+            return Ok(());
+        }
+        let Some(kind) = ValueKind::of(value) else {
+            return Ok(());
+        };
+        let mut seen = self.seen.lock().unwrap();
+        let first = *seen.entry(measurement.to_string()).or_insert(kind);
+        if first != kind {
+            return Err(anyhow!(
+                "Measurement {} was first seen as a {}, rejecting {} value {}",
+                measurement,
+                first,
+                kind,
+                value
+            ));
+        }
+        Ok(())
+    }
+}
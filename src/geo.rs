@@ -0,0 +1,58 @@
+use serde_json::Value;
+
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Reads a latitude/longitude pair from an object with `lat`/`latitude` and
+/// `lon`/`lng`/`longitude` members, a `[lat, lon]` array or a `"lat,lon"`
+/// string, as trackers publish it.
+pub fn parse_location(value: &Value) -> Option<(f64, f64)> {
+    let number = |value: &Value| match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    };
+    let (lat, lon) = match value {
+        Value::Object(object) => {
+            let lat = ["lat", "latitude"].iter().find_map(|key| object.get(*key).and_then(number))?;
+            let lon = ["lon", "lng", "longitude"].iter().find_map(|key| object.get(*key).and_then(number))?;
+            (lat, lon)
+        }
+        Value::Array(pair) if pair.len() == 2 => (number(&pair[0])?, number(&pair[1])?),
+        Value::String(text) => {
+            let (lat, lon) = text.split_once(',')?;
+            (lat.trim().parse().ok()?, lon.trim().parse().ok()?)
+        }
+        _ => return None,
+    };
+    ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)).then_some((lat, lon))
+}
+
+/// Encodes a location as a geohash of `precision` characters.
+pub fn geohash(lat: f64, lon: f64, precision: usize) -> String {
+    let mut lat_range = (-90.0, 90.0);
+    let mut lon_range = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+    let mut bits = 0;
+    let mut index = 0;
+    while hash.len() < precision {
+        // bits alternate between longitude and latitude, starting with longitude
+        let (range, value) = if even { (&mut lon_range, lon) } else { (&mut lat_range, lat) };
+        let mid = (range.0 + range.1) / 2.0;
+        index <<= 1;
+        if value >= mid {
+            index |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            hash.push(BASE32[index] as char);
+            bits = 0;
+            index = 0;
+        }
+    }
+    hash
+}
@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+use crate::pipeline::Point;
+
+/// A piece of a global tag value.
+enum Part {
+    /// Text, including the substitutions resolved once at startup
+    Literal(String),
+    /// `${topic}`, the topic of the message the point was extracted from
+    Topic,
+}
+
+/// Tags added to every point, such as the host or site the bridge runs at.
+/// Values may contain `${hostname}`, `${env:NAME}` and `${topic}`.
+pub struct GlobalTags {
+    tags: Vec<(String, Vec<Part>)>,
+}
+
+impl GlobalTags {
+    pub fn new(tags: Option<&HashMap<String, String>>) -> Result<Self> {
+        let mut parsed = Vec::new();
+        for (tag, value) in tags.into_iter().flatten() {
+            parsed.push((tag.clone(), parse(tag, value)?));
+        }
+        // sorted so the tags are applied in the same order on every run
+        parsed.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(GlobalTags { tags: parsed })
+    }
+
+    /// Adds the tags to a point extracted from a message on `topic`. Tags the
+    /// point already has are kept, so measurements can override global tags.
+    pub fn apply(&self, point: &mut Point, topic: &str) {
+        if self.tags.is_empty() {
+            return;
+        }
+        let tags = point.tags.get_or_insert_with(HashMap::new);
+        for (tag, parts) in &self.tags {
+            tags.entry(tag.clone()).or_insert_with(|| {
+                parts
+                    .iter()
+                    .map(|part| match part {
+                        Part::Literal(text) => text.as_str(),
+                        Part::Topic => topic,
+                    })
+                    .collect()
+            });
+        }
+    }
+}
+
+fn parse(tag: &str, value: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| anyhow!("Unclosed substitution in global tag {}", tag))?;
+        literal.push_str(&rest[..start]);
+        match &rest[start + 2..end] {
+            "topic" => {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Topic);
+            }
+            "hostname" => literal.push_str(
+                &gethostname::gethostname()
+                    .into_string()
+                    .map_err(|_| anyhow!("Failed to read hostname for global tag {}: not valid UTF-8", tag))?,
+            ),
+            substitution => match substitution.strip_prefix("env:") {
+                Some(name) => literal.push_str(&std::env::var(name).map_err(|e| {
+                    anyhow!("Failed to read environment variable {} for global tag {}: {}", name, tag, e)
+                })?),
+                None => return Err(anyhow!("Unknown substitution ${{{}}} in global tag {}", substitution, tag)),
+            },
+        }
+        rest = &rest[end + 1..];
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+use crate::failures::FAILURES;
+use crate::pipeline::Message;
+
+const DEFAULT_INTERVAL_SECS: u64 = 60;
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpPollConfig {
+    pub url: String,
+    /// Topic the responses are treated as published on, defaults to the URL
+    pub topic: Option<String>,
+    pub interval_secs: Option<u64>,
+    pub timeout_secs: Option<u64>,
+    /// Extra request headers, e.g. for API keys
+    pub headers: Option<HashMap<String, String>>,
+}
+
+/// Starts a task per endpoint fetching it on its interval, feeding every
+/// successful response body into `messages` as if it had been published over MQTT.
+pub fn spawn(pollers: &[HttpPollConfig], messages: mpsc::Sender<Message>) -> Result<()> {
+    for poller in pollers {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(poller.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)))
+            .build()?;
+        let poller = poller.clone();
+        let messages = messages.clone();
+        let interval = Duration::from_secs(poller.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS).max(1));
+        info!("Polling {} every {}s", poller.url, interval.as_secs());
+        tokio::spawn(async move {
+            let topic = poller.topic.clone().unwrap_or_else(|| poller.url.clone());
+            let mut ticks = tokio::time::interval(interval);
+            // a slow endpoint delays the next poll instead of causing a burst
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let received_at = Utc::now();
+                match fetch(&client, &poller).await {
+                    Ok(payload) => {
+                        let message = Message {
+                            topic: topic.clone(),
+                            payload,
+                            qos: 0,
+                            retain: false,
+                            received_at,
+                        };
+                        if messages.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Error polling {}: {}", poller.url, e);
+                        FAILURES.record(Some(&topic), &[], &e);
+                    }
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn fetch(client: &reqwest::Client, poller: &HttpPollConfig) -> Result<bytes::Bytes> {
+    let mut request = client.get(&poller.url);
+    for (name, value) in poller.headers.iter().flatten() {
+        request = request.header(name, value);
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Request returned {}", response.status()));
+    }
+    Ok(response.bytes().await?)
+}
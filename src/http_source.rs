@@ -0,0 +1,77 @@
+use anyhow::{anyhow, Result};
+use axum::Router;
+use axum::http::StatusCode;
+use axum::routing::post;
+use bytes::Bytes;
+use chrono::Utc;
+use log::{error, info};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::pipeline::Message;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpSourceConfig {
+    pub listen: String,
+    pub routes: Vec<HttpRouteConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpRouteConfig {
+    /// Path payloads are POSTed to, e.g. `/ingest/envoy`
+    pub path: String,
+    /// Topic the payloads are treated as published on, defaults to the path
+    /// without its leading slash
+    pub topic: Option<String>,
+}
+
+/// Starts an HTTP listener accepting JSON payloads on the configured routes,
+/// feeding each one into `messages` as if it had been published over MQTT.
+pub async fn spawn(config: &HttpSourceConfig, messages: mpsc::Sender<Message>) -> Result<()> {
+    let mut app = Router::new();
+    for (index, route) in config.routes.iter().enumerate() {
+        // axum panics on either when the route is added
+        if !route.path.starts_with('/') {
+            return Err(anyhow!("Invalid HTTP source path {}: it must start with /", route.path));
+        }
+        if config.routes[..index].iter().any(|other| other.path == route.path) {
+            return Err(anyhow!("Invalid HTTP source path {}: it is configured twice", route.path));
+        }
+        let topic = route
+            .topic
+            .clone()
+            .unwrap_or_else(|| route.path.trim_start_matches('/').to_string());
+        let messages = messages.clone();
+        app = app.route(
+            &route.path,
+            post(move |payload: Bytes| ingest(messages.clone(), topic.clone(), payload)),
+        );
+    }
+
+    let listener = tokio::net::TcpListener::bind(&config.listen)
+        .await
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", config.listen, e))?;
+    info!("HTTP source listening on {} for {} routes", config.listen, config.routes.len());
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("HTTP source stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Queues a posted payload, waiting while the bridge is busy so clients are
+/// slowed down rather than their data dropped.
+async fn ingest(messages: mpsc::Sender<Message>, topic: String, payload: Bytes) -> StatusCode {
+    let message = Message {
+        topic,
+        payload,
+        qos: 0,
+        retain: false,
+        received_at: Utc::now(),
+    };
+    match messages.send(message).await {
+        Ok(()) => StatusCode::ACCEPTED,
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
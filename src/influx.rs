@@ -0,0 +1,330 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use influxdb2::api::write::TimestampPrecision;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::InfluxConfig;
+use crate::metrics::InFlightWrite;
+use crate::pipeline::{FieldValue, Point};
+use crate::sink::Sink;
+
+/// The precision point timestamps are written with. Coarser precisions make
+/// writes smaller and match databases expecting them. Dry runs, benchmarks and
+/// dead letters always use nanoseconds.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Precision {
+    #[default]
+    Ns,
+    Us,
+    Ms,
+    S,
+}
+
+impl Precision {
+    /// The timestamp in units of this precision, rounded down.
+    fn units(self, timestamp: &DateTime<Utc>) -> i64 {
+        let nanos = timestamp.timestamp_nanos_opt().unwrap_or_default();
+        let per_unit = match self {
+            Precision::Ns => 1,
+            Precision::Us => 1_000,
+            Precision::Ms => 1_000_000,
+            Precision::S => 1_000_000_000,
+        };
+        nanos.div_euclid(per_unit)
+    }
+
+    /// The `precision` parameter of an InfluxDB 1.x write.
+    fn v1_name(self) -> &'static str {
+        match self {
+            Precision::Ns => "ns",
+            Precision::Us => "u",
+            Precision::Ms => "ms",
+            Precision::S => "s",
+        }
+    }
+
+    /// The `precision` parameter of an InfluxDB 3 write.
+    fn v3_name(self) -> &'static str {
+        match self {
+            Precision::Ns => "nanosecond",
+            Precision::Us => "microsecond",
+            Precision::Ms => "millisecond",
+            Precision::S => "second",
+        }
+    }
+}
+
+/// How many nodes of an InfluxDB Enterprise cluster must acknowledge a write.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Consistency {
+    Any,
+    One,
+    Quorum,
+    All,
+}
+
+impl Consistency {
+    fn name(self) -> &'static str {
+        match self {
+            Consistency::Any => "any",
+            Consistency::One => "one",
+            Consistency::Quorum => "quorum",
+            Consistency::All => "all",
+        }
+    }
+}
+
+/// Where and how InfluxDB 1.x writes are sent.
+pub struct V1Connection {
+    url: String,
+    http: reqwest::Client,
+    /// Username and password, from a `username:password` token
+    auth: Option<(String, String)>,
+    /// Retention policy for buckets that do not name one
+    retention_policy: Option<String>,
+    consistency: Option<Consistency>,
+    precision: Precision,
+}
+
+/// Where InfluxDB 3 writes are sent, for Core and Enterprise.
+pub struct V3Connection {
+    url: String,
+    http: reqwest::Client,
+    token: Option<String>,
+    precision: Precision,
+}
+
+/// A connection to an InfluxDB server of one of the supported versions.
+pub enum InfluxClient {
+    V1(V1Connection),
+    V2(influxdb2::Client, Precision),
+    V3(V3Connection),
+}
+
+impl InfluxClient {
+    pub fn new(config: &InfluxConfig) -> Result<Self> {
+        match config.version {
+            1 => {
+                let auth = config.token.as_deref().and_then(|token| match token.split(':').collect::<Vec<_>>()[..] {
+                    [username, password] => Some((username.to_string(), password.to_string())),
+                    _ => None,
+                });
+                Ok(InfluxClient::V1(V1Connection {
+                    url: config.url.trim_end_matches('/').to_string(),
+                    http: http_client(config).build()?,
+                    auth,
+                    retention_policy: config.retention_policy.clone(),
+                    consistency: config.consistency,
+                    precision: config.precision.unwrap_or_default(),
+                }))
+            }
+            2 => {
+                let client = influxdb2::ClientBuilder::with_builder(
+                    http_client(config),
+                    &config.url,
+                    config.org.as_deref().unwrap_or(""),
+                    config.token.as_deref().unwrap_or(""),
+                )
+                .build()?;
+                Ok(InfluxClient::V2(client, config.precision.unwrap_or_default()))
+            }
+            3 => Ok(InfluxClient::V3(V3Connection {
+                url: config.url.trim_end_matches('/').to_string(),
+                http: http_client(config).build()?,
+                token: config.token.clone().filter(|token| !token.is_empty()),
+                precision: config.precision.unwrap_or_default(),
+            })),
+            _ => Err(anyhow!("Unsupported InfluxDB version: {}", config.version)),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for InfluxClient {
+    async fn ping(&self) -> Result<()> {
+        match self {
+            InfluxClient::V1(connection) => {
+                let response = connection.http.get(format!("{}/ping", connection.url)).send().await?;
+                if !response.status().is_success() {
+                    return Err(anyhow!("Ping returned {}", response.status()));
+                }
+            }
+            InfluxClient::V2(client, _) => {
+                client.ready().await?;
+            }
+            InfluxClient::V3(connection) => {
+                let mut request = connection.http.get(format!("{}/health", connection.url));
+                if let Some(token) = &connection.token {
+                    request = request.bearer_auth(token);
+                }
+                let response = request.send().await?;
+                if !response.status().is_success() {
+                    return Err(anyhow!("Ping returned {}", response.status()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_batch(&self, points: &[Point], bucket: &str) -> Result<()> {
+        match self {
+            InfluxClient::V1(connection) => {
+                let _in_flight = InFlightWrite::start();
+                // a bucket of the form database/retention_policy, as in the 2.x compatibility API
+                let (database, retention_policy) = match bucket.split_once('/') {
+                    Some((database, retention_policy)) => (database, Some(retention_policy)),
+                    None => (bucket, connection.retention_policy.as_deref()),
+                };
+                let mut request = connection
+                    .http
+                    .post(format!("{}/write", connection.url))
+                    .query(&[("db", database), ("precision", connection.precision.v1_name())]);
+                if let Some(retention_policy) = retention_policy {
+                    request = request.query(&[("rp", retention_policy)]);
+                }
+                if let Some(consistency) = connection.consistency {
+                    request = request.query(&[("consistency", consistency.name())]);
+                }
+                if let Some((username, password)) = &connection.auth {
+                    request = request.basic_auth(username, Some(password));
+                }
+                let lines = lines(points, connection.precision, false);
+                check_response(request.body(lines).send().await?).await?;
+            }
+            InfluxClient::V2(client, precision) => {
+                let _in_flight = InFlightWrite::start();
+                let timestamp_precision = match precision {
+                    Precision::Ns => TimestampPrecision::Nanoseconds,
+                    Precision::Us => TimestampPrecision::Microseconds,
+                    Precision::Ms => TimestampPrecision::Milliseconds,
+                    Precision::S => TimestampPrecision::Seconds,
+                };
+                // written as line protocol since the client's data points cannot hold unsigned integers
+                let lines = lines(points, *precision, true);
+                client.write_line_protocol_with_precision(&client.org, bucket, lines, timestamp_precision).await?;
+            }
+            InfluxClient::V3(connection) => {
+                let _in_flight = InFlightWrite::start();
+                let mut request = connection
+                    .http
+                    .post(format!("{}/api/v3/write_lp", connection.url))
+                    .query(&[("db", bucket), ("precision", connection.precision.v3_name())]);
+                if let Some(token) = &connection.token {
+                    request = request.bearer_auth(token);
+                }
+                let lines = lines(points, connection.precision, true);
+                check_response(request.body(lines).send().await?).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Timeouts, connection errors, server errors and rate limiting are
+    /// temporary conditions, while any other response, such as
+    /// `400 Bad Request` for malformed points or `401 Unauthorized`, fails
+    /// again every time.
+    fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        let status = if let Some(rejected) = error.downcast_ref::<WriteRejected>() {
+            rejected.status.as_u16()
+        } else if let Some(influxdb2::RequestError::Http { status, .. }) = error.downcast_ref() {
+            status.as_u16()
+        } else {
+            return true;
+        };
+        matches!(status, 408 | 429 | 500..)
+    }
+}
+
+/// An unsuccessful HTTP response to a write.
+#[derive(Debug)]
+pub struct WriteRejected {
+    status: reqwest::StatusCode,
+    message: String,
+}
+
+impl std::fmt::Display for WriteRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.message.as_str() {
+            "" => write!(f, "InfluxDB returned {}", self.status),
+            message => write!(f, "InfluxDB returned {}: {}", self.status, message),
+        }
+    }
+}
+
+impl std::error::Error for WriteRejected {}
+
+/// Turns an unsuccessful write response into an error carrying the server's message.
+async fn check_response(response: reqwest::Response) -> Result<()> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let message = response.text().await.unwrap_or_default().trim().to_string();
+    Err(WriteRejected { status, message }.into())
+}
+
+/// The HTTP client shared by every write, keeping connections (and their TLS
+/// sessions) alive between requests instead of handshaking per point.
+fn http_client(config: &InfluxConfig) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder()
+        .tcp_keepalive(Duration::from_secs(60))
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_secs.unwrap_or(90)));
+    if let Some(max_idle) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    builder
+}
+
+fn escape_line_protocol(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn line_protocol(point: &Point) -> String {
+    line_protocol_in(point, Precision::Ns, true)
+}
+
+/// Formats points as line protocol, one per line.
+fn lines(points: &[Point], precision: Precision, unsigned: bool) -> String {
+    points.iter().map(|point| line_protocol_in(point, precision, unsigned)).collect::<Vec<_>>().join("\n")
+}
+
+/// Formats a point as line protocol with its timestamp in `precision`. Without
+/// `unsigned`, for InfluxDB 1.x, unsigned integers are written as signed ones.
+fn line_protocol_in(point: &Point, precision: Precision, unsigned: bool) -> String {
+    let mut line = escape_line_protocol(&point.measurement, &[',', ' ']);
+    if let Some(tags) = &point.tags {
+        let mut sorted: Vec<_> = tags.iter().collect();
+        sorted.sort();
+        for (key, val) in sorted {
+            line.push(',');
+            line.push_str(&escape_line_protocol(key, &[',', '=', ' ']));
+            line.push('=');
+            line.push_str(&escape_line_protocol(val, &[',', '=', ' ']));
+        }
+    }
+    for (index, (name, value)) in point.fields.iter().enumerate() {
+        line.push(if index == 0 { ' ' } else { ',' });
+        line.push_str(&escape_line_protocol(name, &[',', '=', ' ']));
+        match value {
+            FieldValue::Float(value) => line.push_str(&format!("={}", value)),
+            FieldValue::Integer(value) => line.push_str(&format!("={}i", value)),
+            FieldValue::UInteger(value) if unsigned => line.push_str(&format!("={}u", value)),
+            FieldValue::UInteger(value) => line.push_str(&format!("={}i", value)),
+            FieldValue::Boolean(flag) => line.push_str(&format!("={}", flag)),
+            FieldValue::String(text) => line.push_str(&format!("=\"{}\"", escape_line_protocol(text, &['"', '\\']))),
+        }
+    }
+    line.push_str(&format!(" {}", precision.units(&point.timestamp)));
+    line
+}
@@ -0,0 +1,260 @@
+#[cfg(not(feature = "kafka"))]
+use anyhow::Result;
+#[cfg(not(feature = "kafka"))]
+use async_trait::async_trait;
+use serde::Deserialize;
+
+#[cfg(not(feature = "kafka"))]
+use crate::pipeline::Point;
+#[cfg(not(feature = "kafka"))]
+use crate::sink::Sink;
+
+#[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+pub struct KafkaOutputConfig {
+    /// Brokers the cluster is discovered from, e.g. `["kafka1:9092", "kafka2:9092"]`
+    pub brokers: Vec<String>,
+    pub topic: String,
+    pub format: Option<KafkaFormat>,
+    pub compression: Option<KafkaCompression>,
+    pub client_id: Option<String>,
+}
+
+/// How a point is encoded in the value of its record.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+pub enum KafkaFormat {
+    /// An object with the measurement, tags, fields and timestamp
+    #[default]
+    Json,
+    /// A line of line protocol with a nanosecond timestamp
+    LineProtocol,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+pub enum KafkaCompression {
+    #[default]
+    None,
+    Gzip,
+    Lz4,
+    Snappy,
+    Zstd,
+}
+
+#[cfg(not(feature = "kafka"))]
+pub struct KafkaOutput;
+
+#[cfg(not(feature = "kafka"))]
+impl KafkaOutput {
+    pub fn new(_config: &KafkaOutputConfig) -> Result<Self> {
+        Err(anyhow::anyhow!("kafka_output is configured but this build lacks the kafka feature"))
+    }
+}
+
+#[cfg(not(feature = "kafka"))]
+#[async_trait]
+impl Sink for KafkaOutput {
+    async fn write_batch(&self, _points: &[Point], _bucket: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub use enabled::KafkaOutput;
+
+#[cfg(feature = "kafka")]
+mod enabled {
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use chrono::SecondsFormat;
+    use log::info;
+    use rskafka::BackoffConfig;
+    use rskafka::client::error::{Error as KafkaError, ProtocolError};
+    use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+    use rskafka::client::ClientBuilder;
+    use rskafka::record::Record;
+    use serde_json::{Map, Value};
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+    use tokio::sync::Mutex;
+
+    use super::{KafkaCompression, KafkaFormat, KafkaOutputConfig};
+    use crate::influx::line_protocol;
+    use crate::pipeline::{FieldValue, Point};
+    use crate::sink::Sink;
+
+    /// How long a produce request keeps retrying before the write fails and is
+    /// retried, buffered or given up on like any other.
+    const PRODUCE_DEADLINE: Duration = Duration::from_secs(10);
+
+    /// Publishes points to a Kafka topic, one record per point keyed by its
+    /// series, so every point of a series lands on the same partition in order.
+    pub struct KafkaOutput {
+        config: KafkaOutputConfig,
+        /// A client for every partition of the topic, connected on the first write
+        partitions: Mutex<Vec<PartitionClient>>,
+        compression: Compression,
+    }
+
+    impl KafkaOutput {
+        pub fn new(config: &KafkaOutputConfig) -> Result<Self> {
+            if config.brokers.is_empty() {
+                return Err(anyhow!("kafka_output needs at least one broker"));
+            }
+            let compression = match config.compression.unwrap_or_default() {
+                KafkaCompression::None => Compression::NoCompression,
+                KafkaCompression::Gzip => Compression::Gzip,
+                KafkaCompression::Lz4 => Compression::Lz4,
+                KafkaCompression::Snappy => Compression::Snappy,
+                KafkaCompression::Zstd => Compression::Zstd,
+            };
+            Ok(KafkaOutput { config: config.clone(), partitions: Mutex::new(Vec::new()), compression })
+        }
+
+        async fn publish(&self, points: &[Point]) -> Result<()> {
+            let mut partitions = self.partitions.lock().await;
+            if partitions.is_empty() {
+                *partitions = self.connect().await?;
+            }
+            let mut records: Vec<Vec<Record>> = partitions.iter().map(|_| Vec::new()).collect();
+            for point in points {
+                let key = series(point);
+                // the partition the Java client's default partitioner picks for the key
+                let partition = (murmur2(key.as_bytes()) & 0x7fffffff) as usize % partitions.len();
+                let value = match self.config.format.unwrap_or_default() {
+                    KafkaFormat::Json => serde_json::to_vec(&json(point))?,
+                    KafkaFormat::LineProtocol => line_protocol(point).into_bytes(),
+                };
+                records[partition].push(Record {
+                    key: Some(key.into_bytes()),
+                    value: Some(value),
+                    headers: BTreeMap::new(),
+                    timestamp: point.timestamp,
+                });
+            }
+            for (partition, records) in partitions.iter().zip(records) {
+                partition.produce(records, self.compression).await.map_err(|e| {
+                    let message = e.to_string();
+                    // kept as the source for `is_permanent`
+                    anyhow::Error::new(e).context(format!("Failed to publish to Kafka: {}", message))
+                })?;
+            }
+            Ok(())
+        }
+
+        async fn connect(&self) -> Result<Vec<PartitionClient>> {
+            let topic = &self.config.topic;
+            let mut builder = ClientBuilder::new(self.config.brokers.clone()).backoff_config(BackoffConfig {
+                deadline: Some(PRODUCE_DEADLINE),
+                ..BackoffConfig::default()
+            });
+            if let Some(client_id) = &self.config.client_id {
+                builder = builder.client_id(client_id.as_str());
+            }
+            let client = builder.build().await.map_err(|e| anyhow!("Failed to connect to Kafka: {}", e))?;
+            let topics = client.list_topics().await.map_err(|e| anyhow!("Failed to list Kafka topics: {}", e))?;
+            let Some(found) = topics.into_iter().find(|found| &found.name == topic) else {
+                return Err(anyhow!("Kafka topic {} does not exist", topic));
+            };
+            let mut partitions = Vec::new();
+            for partition in found.partitions {
+                let client = client
+                    .partition_client(topic.as_str(), partition, UnknownTopicHandling::Retry)
+                    .await
+                    .map_err(|e| anyhow!("Failed to connect to partition {} of {}: {}", partition, topic, e))?;
+                partitions.push(client);
+            }
+            if partitions.is_empty() {
+                return Err(anyhow!("Kafka topic {} has no partitions", topic));
+            }
+            info!("Connected to Kafka topic {} with {} partitions", topic, partitions.len());
+            Ok(partitions)
+        }
+    }
+
+    #[async_trait]
+    impl Sink for KafkaOutput {
+        async fn write_batch(&self, points: &[Point], _bucket: &str) -> Result<()> {
+            self.publish(points).await
+        }
+
+        fn is_retryable(&self, error: &anyhow::Error) -> bool {
+            !is_permanent(error)
+        }
+    }
+
+    /// The measurement and sorted tags, which identify the series of a point.
+    fn series(point: &Point) -> String {
+        let mut tags: Vec<_> = point.tags.iter().flatten().collect();
+        tags.sort();
+        let mut key = point.measurement.clone();
+        for (name, value) in tags {
+            key.push_str(&format!(",{}={}", name, value));
+        }
+        key
+    }
+
+    fn json(point: &Point) -> Value {
+        let fields: Map<String, Value> = point
+            .fields
+            .iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    FieldValue::Float(value) => Value::from(*value),
+                    FieldValue::Integer(value) => Value::from(*value),
+                    FieldValue::UInteger(value) => Value::from(*value),
+                    FieldValue::Boolean(flag) => Value::from(*flag),
+                    FieldValue::String(text) => Value::from(text.as_str()),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        serde_json::json!({
+            "measurement": point.measurement,
+            "tags": point.tags.clone().unwrap_or_default(),
+            "fields": fields,
+            "timestamp": point.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        })
+    }
+
+    /// Whether the brokers refused the records themselves, so publishing them
+    /// again would fail the same way.
+    fn is_permanent(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<KafkaError>(),
+            Some(KafkaError::ServerError {
+                protocol_error: ProtocolError::MessageTooLarge
+                    | ProtocolError::RecordListTooLarge
+                    | ProtocolError::InvalidRecord
+                    | ProtocolError::TopicAuthorizationFailed,
+                ..
+            })
+        )
+    }
+
+    /// Kafka's murmur2 hash of a record key, stable across releases unlike the
+    /// hashers of the standard library.
+    fn murmur2(data: &[u8]) -> u32 {
+        const M: u32 = 0x5bd1e995;
+        let mut h = 0x9747b28c ^ data.len() as u32;
+        let mut chunks = data.chunks_exact(4);
+        for chunk in &mut chunks {
+            let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]).wrapping_mul(M);
+            k ^= k >> 24;
+            h = h.wrapping_mul(M) ^ k.wrapping_mul(M);
+        }
+        let rest = chunks.remainder();
+        if !rest.is_empty() {
+            for (index, byte) in rest.iter().enumerate().rev() {
+                h ^= u32::from(*byte) << (8 * index);
+            }
+            h = h.wrapping_mul(M);
+        }
+        h ^= h >> 13;
+        h = h.wrapping_mul(M);
+        h ^ (h >> 15)
+    }
+}
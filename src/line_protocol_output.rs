@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::influx::line_protocol;
+use crate::pipeline::Point;
+use crate::sink::Sink;
+
+const DEFAULT_MAX_FILES: usize = 5;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LineProtocolOutputConfig {
+    /// File the points are appended to, `-` for stdout
+    pub path: String,
+    /// Size at which the file is rotated, never by default
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+struct OpenFile {
+    file: File,
+    len: u64,
+}
+
+/// Writes points as line protocol to stdout or a file, to debug mappings or
+/// to feed `influx write` and Telegraf.
+///
+/// A file reaching `max_bytes` is renamed to `path.1`, shifting older files up
+/// to `path.<max_files>`, and a new one is started.
+pub struct LineProtocolOutput {
+    path: String,
+    /// `None` for stdout
+    file: Option<Mutex<OpenFile>>,
+    max_bytes: Option<u64>,
+    max_files: usize,
+}
+
+impl LineProtocolOutput {
+    pub fn open(config: &LineProtocolOutputConfig) -> Result<Self> {
+        let file = match config.path.as_str() {
+            "-" => None,
+            path => Some(Mutex::new(open(path)?)),
+        };
+        Ok(LineProtocolOutput {
+            path: config.path.clone(),
+            file,
+            max_bytes: config.max_bytes,
+            max_files: config.max_files.unwrap_or(DEFAULT_MAX_FILES).max(1),
+        })
+    }
+
+    fn write_lines(&self, lines: &str) -> Result<()> {
+        let Some(file) = &self.file else {
+            println!("{}", lines);
+            return Ok(());
+        };
+        let mut file = file.lock().unwrap();
+        let size = lines.len() as u64 + 1;
+        if let Some(max_bytes) = self.max_bytes
+            && file.len > 0
+            && file.len + size > max_bytes
+        {
+            *file = self.rotate()?;
+        }
+        writeln!(file.file, "{}", lines)
+            .map_err(|e| anyhow!("Failed to write to line protocol file {}: {}", self.path, e))?;
+        file.len += size;
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<OpenFile> {
+        for index in (1..self.max_files).rev() {
+            let from = format!("{}.{}", self.path, index);
+            if fs::metadata(&from).is_ok() {
+                fs::rename(&from, format!("{}.{}", self.path, index + 1))
+                    .map_err(|e| anyhow!("Failed to rotate line protocol file {}: {}", from, e))?;
+            }
+        }
+        fs::rename(&self.path, format!("{}.1", self.path))
+            .map_err(|e| anyhow!("Failed to rotate line protocol file {}: {}", self.path, e))?;
+        open(&self.path)
+    }
+}
+
+#[async_trait]
+impl Sink for LineProtocolOutput {
+    async fn write_batch(&self, points: &[Point], _bucket: &str) -> Result<()> {
+        self.write_lines(&points.iter().map(line_protocol).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+fn open(path: &str) -> Result<OpenFile> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open line protocol file {}: {}", path, e))?;
+    let len = file.metadata().map_err(|e| anyhow!("Failed to read line protocol file {}: {}", path, e))?.len();
+    Ok(OpenFile { file, len })
+}
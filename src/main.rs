@@ -1,23 +1,217 @@
 use anyhow::{anyhow, Result};
-use clap::Parser;
-use evalexpr::{eval_with_context_mut, HashMapContext, Value, ContextWithMutableVariables};
-use jsonpath_rust::JsonPathFinder;
-use log::{debug, error, info};
-use rumqttc::{AsyncClient, MqttOptions, QoS, Event, Packet};
+use clap::{Parser, Subcommand};
+use log::{debug, error, info, warn};
+use rumqttc::{AsyncClient, MqttOptions, QoS, Event, Outgoing, Packet};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::mpsc;
+
+mod admin;
+mod amqp_source;
+mod availability;
+mod bench;
+mod binary;
+mod bounds;
+mod coap_source;
+mod compression;
+mod counters;
+mod dead_letter;
+mod dedup;
+mod decoder;
+mod delimited;
+mod disk_buffer;
+mod exactly_once;
+mod failures;
+mod field_types;
+mod geo;
+mod global_tags;
+mod http_poll;
+mod http_source;
+mod influx;
+mod kafka_output;
+mod line_protocol_output;
+mod metrics;
+mod mqtt;
+mod name_template;
+mod persistent;
+mod pipeline;
+mod postgres_output;
+mod presets;
+mod prometheus_exporter;
+mod protobuf;
+mod queue;
+mod record;
+mod retained;
+mod sanitize;
+mod script;
+mod simulate;
+mod sink;
+mod state;
+mod timestamp;
+mod topic_filter;
+mod topics;
+mod udp_source;
+mod units;
+mod workers;
+mod writer;
+mod xml;
+
+use amqp_source::AmqpSourceConfig;
+use availability::AvailabilityConfig;
+use binary::{BinaryType, Endianness};
+use coap_source::CoapSourceConfig;
+use compression::Compression;
+use dedup::Deduplicator;
+use delimited::Column;
+use disk_buffer::DiskBufferConfig;
+use exactly_once::ExactlyOnceConfig;
+use kafka_output::{KafkaOutput, KafkaOutputConfig};
+use line_protocol_output::{LineProtocolOutput, LineProtocolOutputConfig};
+use postgres_output::{PostgresOutput, PostgresOutputConfig};
+use prometheus_exporter::PrometheusExporterConfig;
+use field_types::FieldTypeMismatch;
+use http_poll::HttpPollConfig;
+use http_source::HttpSourceConfig;
+use influx::{Consistency, InfluxClient, Precision};
+use mqtt::{Backoff, BrokerConfig, MqttConnection, MqttEvent, MqttTransport};
+use pipeline::{FieldType, Message, PayloadFormat, Pipeline};
+use presets::PresetsConfig;
+use record::{RecordedMessage, Recorder, RecordingReader};
+use retained::{RetainedFilter, RetainedPolicy};
+use sanitize::SanitizeMode;
+use simulate::SimulatorConfig;
+use sink::{DryRun, Sink, SinkRouteConfig};
+use timestamp::TimestampFormat;
+use topics::TopicPipelines;
+use udp_source::UdpSourceConfig;
+use workers::WorkerPool;
+use writer::{PointWriter, Writers, extract_and_write};
 
 #[derive(Debug, Deserialize, Clone)]
 struct Config {
     mqtt_host: String,
     mqtt_port: u16,
-    mqtt_topic: String,
+    mqtt_topic: Option<String>,
+    mqtt_topics: Option<Vec<TopicConfig>>,
+    mqtt_version: Option<u8>,
+    mqtt_receive_maximum: Option<u16>,
+    mqtt_topic_alias_maximum: Option<u16>,
+    mqtt_transport: Option<MqttTransport>,
+    mqtt_ws_path: Option<String>,
+    mqtt_client_id: Option<String>,
+    mqtt_client_id_suffix: Option<bool>,
+    mqtt_clean_session: Option<bool>,
+    mqtt_session_expiry_secs: Option<u32>,
+    mqtt_username: Option<String>,
+    mqtt_password: Option<String>,
+    mqtt_tls: Option<bool>,
+    mqtt_ca_cert: Option<String>,
+    mqtt_tls_skip_verify: Option<bool>,
+    mqtt_client_cert: Option<String>,
+    mqtt_client_key: Option<String>,
+    mqtt_qos: Option<u8>,
+    mqtt_keep_alive_secs: Option<u64>,
+    mqtt_inflight: Option<u16>,
+    mqtt_max_packet_size: Option<u32>,
+    mqtt_shared_group: Option<String>,
+    mqtt_retained: Option<RetainedPolicy>,
+    mqtt_status_topic: Option<String>,
+    mqtt_status_online: Option<String>,
+    mqtt_status_offline: Option<String>,
+    mqtt_reconnect_initial_ms: Option<u64>,
+    mqtt_reconnect_max_ms: Option<u64>,
+    mqtt_reconnect_jitter: Option<bool>,
+    brokers: Option<Vec<BrokerConfig>>,
     log_level: Option<String>,
+    timezone: Option<String>,
+    timestamp_format: Option<TimestampFormat>,
     terminate_on_error: Option<bool>,
-    influxdb: InfluxConfig,
+    admin_listen: Option<String>,
+    failure_history: Option<usize>,
+    workers: Option<usize>,
+    writers: Option<usize>,
+    simd_json: Option<bool>,
+    protobuf_descriptors: Option<Vec<String>>,
+    compression: Option<Compression>,
+    lossy_utf8: Option<bool>,
+    csv_delimiter: Option<String>,
+    csv_header: Option<bool>,
+    global_tags: Option<HashMap<String, String>>,
+    sanitize: Option<SanitizeMode>,
+    sanitize_replacement: Option<String>,
+    field_type_mismatch: Option<FieldTypeMismatch>,
+    queue_high_water: Option<usize>,
+    queue_low_water: Option<usize>,
+    per_topic_pipelines: Option<bool>,
+    dedup_window_secs: Option<u64>,
+    buffer_max_points: Option<usize>,
+    buffer_max_bytes: Option<usize>,
+    buffer_overflow: Option<OverflowPolicy>,
+    write_retries: Option<u32>,
+    write_retry_delay_ms: Option<u64>,
+    write_retry_max_delay_ms: Option<u64>,
+    batch_size: Option<usize>,
+    flush_interval_ms: Option<u64>,
+    dead_letter: Option<String>,
+    disk_buffer: Option<DiskBufferConfig>,
+    state_file: Option<String>,
+    state_save_interval_secs: Option<u64>,
+    influxdb: Option<InfluxConfig>,
+    influxdb_targets: Option<Vec<InfluxTargetConfig>>,
+    line_protocol_output: Option<LineProtocolOutputConfig>,
+    prometheus_exporter: Option<PrometheusExporterConfig>,
+    postgres_output: Option<PostgresOutputConfig>,
+    kafka_output: Option<KafkaOutputConfig>,
+    sink_routes: Option<Vec<SinkRouteConfig>>,
+    exactly_once: Option<ExactlyOnceConfig>,
+    http_source: Option<HttpSourceConfig>,
+    udp_source: Option<UdpSourceConfig>,
+    coap_source: Option<CoapSourceConfig>,
+    amqp_source: Option<AmqpSourceConfig>,
+    http_poll: Option<Vec<HttpPollConfig>>,
+    runtime: Option<RuntimeConfig>,
+    presets: Option<PresetsConfig>,
+    availability: Option<AvailabilityConfig>,
+    #[serde(default)]
     measurements: Vec<MeasurementConfig>,
+    simulate: Option<Vec<SimulatorConfig>>,
+}
+
+/// A subscription, optionally evaluated against only some of the measurements.
+#[derive(Debug, Deserialize, Clone)]
+struct TopicConfig {
+    /// A topic filter, or a shared subscription `$share/<group>/<filter>` the
+    /// broker balances across every client subscribed with the same group
+    topic: String,
+    /// The maximum QoS of delivered messages. For a shared subscription the
+    /// broker delivers each message to only one member at this QoS, so with
+    /// QoS 0 a message sent to a member that then disconnects is lost
+    qos: Option<u8>,
+    /// Names of the measurements evaluated for messages on this topic, all when left out
+    measurements: Option<Vec<String>>,
+    retained: Option<RetainedPolicy>,
+    /// Payload format of messages on this topic, for measurements without their own
+    format: Option<PayloadFormat>,
+    protobuf_message: Option<String>,
+    compression: Option<Compression>,
+    /// A Rhai script producing points from every message on this topic
+    script: Option<String>,
+}
+
+/// What happens when the in-memory write buffer is full.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum OverflowPolicy {
+    /// Wait for the writer to catch up, which stops reading from the broker
+    #[default]
+    Block,
+    /// Discard the oldest buffered points to make room
+    DropOldest,
+    /// Discard the points that do not fit, keeping the buffered ones
+    DropNewest,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -27,163 +221,699 @@ struct InfluxConfig {
     bucket: String,
     org: Option<String>,
     token: Option<String>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout_secs: Option<u64>,
+    startup_check: Option<StartupCheck>,
+    precision: Option<Precision>,
+    retention_policy: Option<String>,
+    consistency: Option<Consistency>,
+}
+
+/// An InfluxDB server points are written to, with the failure handling that
+/// applies to it alone.
+#[derive(Debug, Deserialize, Clone)]
+struct InfluxTargetConfig {
+    /// Used in log messages and to route points to the target, defaults to the URL
+    name: Option<String>,
+    #[serde(flatten)]
+    influxdb: InfluxConfig,
+    dead_letter: Option<String>,
+    disk_buffer: Option<DiskBufferConfig>,
+}
+
+impl Config {
+    /// Every server points are written to: `[influxdb]` with the top-level
+    /// `dead_letter` and `disk_buffer`, followed by the `[[influxdb_targets]]`.
+    fn targets(&self) -> Vec<InfluxTargetConfig> {
+        let primary = self.influxdb.iter().map(|influxdb| InfluxTargetConfig {
+            name: None,
+            influxdb: influxdb.clone(),
+            dead_letter: self.dead_letter.clone(),
+            disk_buffer: self.disk_buffer.clone(),
+        });
+        let others = self.influxdb_targets.iter().flatten().map(|target| InfluxTargetConfig {
+            name: Some(target.name.clone().unwrap_or_else(|| target.influxdb.url.clone())),
+            ..target.clone()
+        });
+        primary.chain(others).collect()
+    }
+
+    /// The names measurements and `[[sink_routes]]` choose sinks by: those of
+    /// the InfluxDB targets and the sections of the other outputs configured.
+    fn sink_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.targets().iter().map(InfluxTargetConfig::sink_name).collect();
+        let outputs = [
+            ("line_protocol_output", self.line_protocol_output.is_some()),
+            ("prometheus_exporter", self.prometheus_exporter.is_some()),
+            ("postgres_output", self.postgres_output.is_some()),
+            ("kafka_output", self.kafka_output.is_some()),
+        ];
+        names.extend(outputs.iter().filter(|(_, configured)| *configured).map(|(name, _)| name.to_string()));
+        names
+    }
+}
+
+impl InfluxTargetConfig {
+    /// `influxdb` for the `[influxdb]` section, the name of the others.
+    fn sink_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| "influxdb".to_string())
+    }
+}
+
+/// Whether InfluxDB is checked for before subscribing to the broker.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum StartupCheck {
+    /// Subscribe straight away
+    #[default]
+    None,
+    /// Check once and log a warning if InfluxDB is unreachable
+    Warn,
+    /// Wait until InfluxDB is reachable before subscribing
+    Wait,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct RuntimeConfig {
+    worker_threads: Option<usize>,
+    max_blocking_threads: Option<usize>,
+}
+
+/// A field of a measurement writing several: a JSONPath, or a table with the
+/// path and an expression.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+enum FieldConfig {
+    Path(String),
+    Detailed {
+        path: String,
+        expression: Option<String>,
+        field_type: Option<FieldType>,
+        min: Option<f64>,
+        max: Option<f64>,
+        max_step: Option<f64>,
+    },
 }
 
 #[derive(Debug, Deserialize, Clone)]
 struct MeasurementConfig {
     name: String,
-    path: String,
+    topic: Option<String>,
+    format: Option<PayloadFormat>,
+    protobuf_message: Option<String>,
+    path: Option<String>,
+    column: Option<Column>,
+    field: Option<String>,
+    field_type: Option<FieldType>,
+    true_values: Option<Vec<String>>,
+    false_values: Option<Vec<String>>,
+    /// Values strings such as `heat` are replaced with, compared ignoring case
+    map: Option<HashMap<String, serde_json::Value>>,
+    /// A tag holding the string a mapped value was read as
+    map_tag: Option<String>,
+    fields: Option<HashMap<String, FieldConfig>>,
+    iterate: Option<String>,
+    offset: Option<usize>,
+    #[serde(rename = "type")]
+    value_type: Option<BinaryType>,
+    endianness: Option<Endianness>,
+    scale: Option<f64>,
     expression: Option<String>,
+    /// Points for which this evaluates to false are not written
+    filter: Option<String>,
+    /// Readings below `min`, above `max` or further than `max_step` from the previous one are dropped
+    min: Option<f64>,
+    max: Option<f64>,
+    max_step: Option<f64>,
+    /// A Rhai script producing the points instead of `path` or `fields`
+    script: Option<String>,
+    /// Variables for the expressions, by name, read with JSONPaths like `path`
+    inputs: Option<HashMap<String, String>>,
+    /// Variables the expressions keep per series between messages, with their initial values
+    variables: Option<HashMap<String, serde_json::Value>>,
     tags: Option<HashMap<String, String>>,
+    index_tag: Option<String>,
+    match_tags: Option<HashMap<String, String>>,
+    bucket: Option<String>,
+    /// Names of the sinks the points are written to, all when left out
+    sinks: Option<Vec<String>>,
+    timestamp_path: Option<String>,
+    timestamp_format: Option<TimestampFormat>,
+    timezone: Option<String>,
+    event: Option<bool>,
+    location: Option<bool>,
+    geohash_precision: Option<usize>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Path to the configuration file
+    #[arg(short, long, default_value = "config.toml", global = true)]
+    config: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Bridge MQTT messages into InfluxDB (the default)
+    Run,
+    /// Record raw MQTT traffic to a newline-delimited JSON file without writing to InfluxDB
+    Record {
+        /// File to append the recorded messages to
+        output: String,
+    },
+    /// Feed a recorded message file through the measurement pipeline
+    Replay {
+        /// Recording file produced by the record subcommand
+        input: String,
+        /// Reproduce the original gaps between messages instead of replaying as fast as possible
+        #[arg(long)]
+        realtime: bool,
+        /// Print line protocol to stdout instead of writing to InfluxDB
+        #[arg(long)]
+        dry_run: bool,
+        /// Timestamp points with the recorded receipt time instead of the current time
+        #[arg(long)]
+        backfill: bool,
+    },
+    /// Publish synthetic payloads from the [[simulate]] config entries to the broker
+    Simulate {
+        /// Stop after each simulator has published this many messages
+        #[arg(long)]
+        count: Option<u64>,
+    },
+    /// Measure pipeline throughput against a sink that discards the points
+    Bench {
+        /// Number of messages to run through the pipeline
+        #[arg(long, default_value_t = 10000)]
+        count: usize,
+        /// Recording file to take messages from instead of the [[simulate]] templates
+        #[arg(long)]
+        input: Option<String>,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let config_content = fs::read_to_string(&args.config)
+        .map_err(|e| anyhow!("Failed to read config file {}: {}", args.config, e))?;
+    let config: Config = toml::from_str(&config_content)?;
+
+    let log_level = config.log_level.as_deref().unwrap_or("info");
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or(log_level));
+
+    let runtime = build_runtime(config.runtime.as_ref())?;
+
+    runtime.block_on(async {
+        match args.command.unwrap_or(Command::Run) {
+            Command::Run => run_bridge(&config).await,
+            Command::Record { output } => run_record(&config, &output).await,
+            Command::Replay { input, realtime, dry_run, backfill } => {
+                run_replay(&config, &input, realtime, dry_run, backfill).await
+            }
+            Command::Simulate { count } => run_simulate(&config, count).await,
+            Command::Bench { count, input } => bench::run_bench(&config, count, input.as_deref()).await,
+        }
+    })
 }
 
-enum InfluxClient {
-    V1(influxdb::Client),
-    V2(influxdb2::Client),
+/// Builds the tokio runtime, sized to the available cores unless configured otherwise.
+fn build_runtime(config: Option<&RuntimeConfig>) -> Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(config) = config {
+        if let Some(worker_threads) = config.worker_threads {
+            builder.worker_threads(worker_threads.max(1));
+        }
+        if let Some(max_blocking_threads) = config.max_blocking_threads {
+            builder.max_blocking_threads(max_blocking_threads.max(1));
+        }
+    }
+    builder
+        .build()
+        .map_err(|e| anyhow!("Failed to start the tokio runtime: {}", e))
 }
 
-impl InfluxClient {
-    fn new(config: &InfluxConfig) -> Self {
-        match config.version {
-            1 => {
-                let client = influxdb::Client::new(&config.url, &config.bucket);
-                let client = if let Some(token) = &config.token {
-                    let parts: Vec<&str> = token.split(':').collect();
-                    if parts.len() == 2 {
-                        client.with_auth(parts[0], parts[1])
-                    } else {
-                        client
+fn mqtt_options(config: &Config, client_id: &str) -> Result<MqttOptions> {
+    let mut mqttoptions = MqttOptions::new(client_id, mqtt::broker_address(config), config.mqtt_port);
+    mqttoptions.set_keep_alive(mqtt::keep_alive(config)?);
+    mqttoptions.set_transport(mqtt::transport(config)?);
+    mqttoptions.set_clean_session(mqtt::clean_session(config)?);
+    if let Some(inflight) = mqtt::inflight(config)? {
+        mqttoptions.set_inflight(inflight);
+    }
+    if let Some(max_packet_size) = config.mqtt_max_packet_size {
+        mqttoptions.set_max_packet_size(max_packet_size as usize, max_packet_size as usize);
+    }
+    if let Some((username, password)) = mqtt::credentials(config) {
+        mqttoptions.set_credentials(username, password);
+    }
+    Ok(mqttoptions)
+}
+
+/// The configured topics plus the topics of measurements and any enabled
+/// presets, with the QoS each is subscribed at.
+fn subscriptions(config: &Config) -> Result<Vec<(String, QoS)>> {
+    let mut topics: Vec<(String, QoS)> = Vec::new();
+    let group = config.mqtt_shared_group.as_deref();
+    let default_qos = rumqttc::qos(config.mqtt_qos.unwrap_or(1))
+        .map_err(|_| anyhow!("Invalid mqtt_qos {}", config.mqtt_qos.unwrap_or(1)))?;
+    let mut add = |topic: &str, qos: QoS| -> Result<()> {
+        if topic.starts_with("$share/") && topic_filter::shared(topic).is_none() {
+            return Err(anyhow!("Invalid shared subscription {}, expected $share/<group>/<filter>", topic));
+        }
+        let mut topic = topic_filter::subscription(topic);
+        if let Some(group) = group
+            && !topic.starts_with("$share/")
+        {
+            topic = format!("$share/{}/{}", group, topic);
+        }
+        if !topics.iter().any(|(existing, _)| *existing == topic) {
+            topics.push((topic, qos));
+        }
+        Ok(())
+    };
+    if let Some(group) = group
+        && (group.is_empty() || group.contains(['/', '+', '#']))
+    {
+        return Err(anyhow!("Invalid mqtt_shared_group {}, it must not be empty or contain /, + or #", group));
+    }
+    if let Some(topic) = &config.mqtt_topic {
+        add(topic, default_qos)?;
+    }
+    for topic in config.mqtt_topics.iter().flatten() {
+        let qos = match topic.qos {
+            Some(qos) => rumqttc::qos(qos).map_err(|_| anyhow!("Invalid QoS {} for topic {}", qos, topic.topic))?,
+            None => default_qos,
+        };
+        add(&topic.topic, qos)?;
+    }
+    for topic in config.measurements.iter().filter_map(|m| m.topic.as_deref()) {
+        add(topic, default_qos)?;
+    }
+    for preset in presets::build(config) {
+        for topic in preset.topics() {
+            add(topic, default_qos)?;
+        }
+    }
+    if topics.is_empty() {
+        return Err(anyhow!("No topics to subscribe to, set mqtt_topic, mqtt_topics or a measurement topic"));
+    }
+    Ok(topics)
+}
+
+fn topic_list(topics: &[(String, QoS)]) -> String {
+    topics.iter().map(|(topic, _)| topic.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+/// Connects to every configured broker and polls each in its own task, merging
+/// their publishes into one channel. A broker that fails is reconnected to
+/// with backoff, unless `terminate_on_error` is set, in which case the error is
+/// passed on.
+///
+/// The channel is bounded, so a consumer that stops receiving also stops the
+/// event loops from being polled.
+fn connect_brokers(config: &Config) -> Result<mpsc::Receiver<Result<rumqttc::Publish>>> {
+    let terminate_on_error = config.terminate_on_error.unwrap_or(false);
+    let (tx, rx) = mpsc::channel(SOURCE_QUEUE_SIZE);
+    for (name, broker) in mqtt::brokers(config) {
+        let client_id = mqtt::client_id(&broker);
+        let mut connection = MqttConnection::new(&broker, &client_id)?;
+        let topics = subscriptions(&broker)?;
+        let mut backoff = Backoff::new(&broker);
+        let status = mqtt::status(&broker);
+        let retained = RetainedFilter::new(&broker);
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut subscribed = false;
+            loop {
+                match connection.poll().await {
+                    Ok(MqttEvent::Publish(publish)) if !retained.accept(&publish) => {
+                        debug!("Skipping retained message on {}", publish.topic);
                     }
-                } else {
-                    client
-                };
-                InfluxClient::V1(client)
+                    Ok(MqttEvent::Publish(publish)) => {
+                        if tx.send(Ok(publish)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(MqttEvent::Connected { session_present }) => {
+                        backoff.reset();
+                        // the will may have been published while the bridge was disconnected
+                        if let Some(status) = &status {
+                            connection.publish_retained(&status.topic, &status.online);
+                        }
+                        // a new session has lost the subscriptions of the previous one
+                        if session_present && subscribed {
+                            info!("Reconnected to MQTT at {} as {}, resuming the session", name, client_id);
+                        } else {
+                            connection.subscribe_all(&topics);
+                            subscribed = true;
+                            info!("Connected to MQTT at {} as {} and subscribed to {}", name, client_id, topic_list(&topics));
+                        }
+                    }
+                    Ok(MqttEvent::Other) => {}
+                    Err(e) if terminate_on_error => {
+                        error!("Error in event loop for {}: {}", name, e);
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                    Err(e) => {
+                        let delay = backoff.next_delay();
+                        error!("Error in event loop for {}, reconnecting in {:.1}s: {}", name, delay.as_secs_f64(), e);
+                        tokio::time::sleep(delay).await;
+                    }
+                }
             }
-            2 => {
-                let client = influxdb2::Client::new(
-                    &config.url,
-                    config.org.as_deref().unwrap_or(""),
-                    config.token.as_deref().unwrap_or(""),
-                );
-                InfluxClient::V2(client)
+        });
+    }
+    Ok(rx)
+}
+
+/// A writer for every InfluxDB target and other output, or for a dry run a
+/// single one printing the points.
+async fn point_writers(config: &Config, dry_run: bool) -> Result<Vec<Arc<PointWriter>>> {
+    if dry_run {
+        return Ok(vec![Arc::new(PointWriter::plain(Arc::new(DryRun), config, None, None, ""))]);
+    }
+    let mut point_writers = Vec::new();
+    for target in config.targets() {
+        let sink = Arc::new(InfluxClient::new(&target.influxdb)?);
+        point_writers.push(Arc::new(PointWriter::new(sink, config, &target)?));
+    }
+    // named after their section, as in `Config::sink_names`
+    let mut outputs: Vec<(Arc<dyn Sink>, &str, String)> = Vec::new();
+    if let Some(output) = &config.line_protocol_output {
+        outputs.push((Arc::new(LineProtocolOutput::open(output)?), "line_protocol_output", output.path.clone()));
+    }
+    if let Some(exporter) = &config.prometheus_exporter {
+        let sink = prometheus_exporter::spawn(exporter).await?;
+        outputs.push((sink, "prometheus_exporter", "the Prometheus exporter".to_string()));
+    }
+    if let Some(output) = &config.postgres_output {
+        let name = format!("PostgreSQL table {}", output.table);
+        outputs.push((Arc::new(PostgresOutput::new(output)?), "postgres_output", name));
+    }
+    if let Some(output) = &config.kafka_output {
+        let name = format!("Kafka topic {}", output.topic);
+        outputs.push((Arc::new(KafkaOutput::new(output)?), "kafka_output", name));
+    }
+    for (sink, sink_name, name) in outputs {
+        point_writers.push(Arc::new(PointWriter::plain(sink, config, Some(sink_name), Some(name), "")));
+    }
+    if point_writers.is_empty() {
+        return Err(anyhow!("Nothing to write points to, add an [influxdb] section or another output"));
+    }
+    Ok(point_writers)
+}
+
+/// Checks InfluxDB is reachable before subscribing, so on a cold boot the
+/// bridge does not pull a large queued backlog it cannot write yet.
+async fn check_influx(influxdb: &InfluxConfig) -> Result<()> {
+    let check = influxdb.startup_check.unwrap_or_default();
+    if check == StartupCheck::None {
+        return Ok(());
+    }
+    let influx_client = InfluxClient::new(influxdb)?;
+    loop {
+        match influx_client.ping().await {
+            Ok(()) => {
+                info!("InfluxDB at {} is reachable", influxdb.url);
+                return Ok(());
+            }
+            Err(e) if check == StartupCheck::Wait => {
+                warn!("InfluxDB at {} is not reachable yet, retrying in 5 seconds: {}", influxdb.url, e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
             }
-            _ => panic!("Unsupported InfluxDB version: {}", config.version),
+            Err(e) => {
+                warn!("InfluxDB at {} is not reachable, subscribing anyway: {}", influxdb.url, e);
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Messages from sources other than the MQTT subscription waiting to be dispatched
+const SOURCE_QUEUE_SIZE: usize = 100;
+
+/// Where incoming messages are handed off for processing.
+enum Dispatcher {
+    /// Topic-partitioned workers feeding the shared writer queue
+    Workers(WorkerPool, Arc<Writers>),
+    /// One independent pipeline per subscribed topic filter
+    Topics(TopicPipelines),
+}
+
+impl Dispatcher {
+    async fn dispatch(&self, message: Message) -> Result<()> {
+        match self {
+            Dispatcher::Workers(pool, _) => pool.dispatch(message).await,
+            Dispatcher::Topics(topics) => topics.dispatch(message).await,
         }
     }
 
-    async fn write(&self, measurement: &str, value: f64, bucket: &str, tags: &Option<HashMap<String, String>>) -> Result<()> {
+    /// Number of points waiting to be written, for the queue water marks.
+    fn queued_points(&self) -> usize {
         match self {
-            InfluxClient::V1(client) => {
-                let mut query = influxdb::WriteQuery::new(chrono::Utc::now().into(), measurement)
-                    .add_field("value", value);
-                if let Some(tags) = tags {
-                    for (key, val) in tags {
-                        query = query.add_tag(key.clone(), val.clone());
-                    }
+            Dispatcher::Workers(_, points) => points.len(),
+            Dispatcher::Topics(topics) => topics.len(),
+        }
+    }
+}
+
+async fn run_bridge(config: &Config) -> Result<()> {
+    if let Some(history) = config.failure_history {
+        failures::FAILURES.set_history(history);
+    }
+    if let Some(listen) = &config.admin_listen {
+        admin::spawn(listen).await?;
+    }
+    if let Some(path) = &config.state_file {
+        state::spawn(path, Duration::from_secs(config.state_save_interval_secs.unwrap_or(30).max(1)));
+    }
+    for target in config.targets() {
+        check_influx(&target.influxdb).await?;
+    }
+
+    if let Some(exactly_once) = &config.exactly_once {
+        if config.http_source.is_some()
+            || config.udp_source.is_some()
+            || config.coap_source.is_some()
+            || config.amqp_source.is_some()
+            || config.http_poll.is_some()
+        {
+            warn!("Sources other than MQTT are not available in exactly-once mode");
+        }
+        if config.brokers.is_some() {
+            warn!("Exactly-once mode only connects to the top-level broker, [[brokers]] are ignored");
+        }
+        if config.mqtt_version == Some(5) {
+            warn!("Exactly-once mode connects with MQTT 3.1.1, mqtt_version = 5 is ignored");
+        }
+        if config.influxdb_targets.is_some()
+            || config.line_protocol_output.is_some()
+            || config.prometheus_exporter.is_some()
+            || config.postgres_output.is_some()
+            || config.kafka_output.is_some()
+        {
+            warn!("Exactly-once mode only writes to [influxdb], other targets and outputs are ignored");
+        }
+        return exactly_once::run_exactly_once(config, exactly_once).await;
+    }
+
+    let pipeline = Arc::new(Pipeline::new(config)?);
+    let point_writers = point_writers(config, false).await?;
+    for point_writer in &point_writers {
+        let replay_writer = point_writer.clone();
+        tokio::spawn(async move { replay_writer.replay_disk_buffer().await });
+    }
+
+    let shared_config = Arc::new(config.clone());
+
+    let (fatal_tx, mut fatal_rx) = mpsc::channel(1);
+    let dispatcher = if config.per_topic_pipelines.unwrap_or(false) {
+        let mut filters: Vec<String> = Vec::new();
+        for (_, broker) in mqtt::brokers(config) {
+            for (filter, _) in subscriptions(&broker)? {
+                if !filters.contains(&filter) {
+                    filters.push(filter);
                 }
-                client.query(query).await.map_err(|e: influxdb::Error| anyhow!(e))?;
             }
-            InfluxClient::V2(client) => {
-                let mut builder = influxdb2::models::DataPoint::builder(measurement)
-                    .field("value", value);
-                if let Some(tags) = tags {
-                    for (key, val) in tags {
-                        builder = builder.tag(key, val);
-                    }
+        }
+        Dispatcher::Topics(TopicPipelines::new(filters, pipeline, shared_config, point_writers, fatal_tx))
+    } else {
+        let points = Writers::spawn(point_writers, shared_config.clone(), fatal_tx.clone());
+        let workers = config.workers.unwrap_or(1);
+        let pool = WorkerPool::spawn(workers, pipeline, shared_config, points.clone(), fatal_tx);
+        Dispatcher::Workers(pool, points)
+    };
+
+    // the sender is kept here too, so the channel stays open without any sources
+    let (source_tx, mut source_rx) = mpsc::channel::<Message>(SOURCE_QUEUE_SIZE);
+    if let Some(http_source) = &config.http_source {
+        http_source::spawn(http_source, source_tx.clone()).await?;
+    }
+    if let Some(udp_source) = &config.udp_source {
+        udp_source::spawn(udp_source, source_tx.clone()).await?;
+    }
+    if let Some(coap_source) = &config.coap_source {
+        coap_source::spawn(coap_source, source_tx.clone()).await?;
+    }
+    if let Some(amqp_source) = &config.amqp_source {
+        amqp_source::spawn(amqp_source, source_tx.clone()).await?;
+    }
+    if let Some(pollers) = &config.http_poll {
+        http_poll::spawn(pollers, source_tx.clone())?;
+    }
+
+    let mut publishes = connect_brokers(config)?;
+
+    let high_water = config.queue_high_water;
+    let low_water = config.queue_low_water.or(high_water.map(|h| h / 2)).unwrap_or(0);
+    let mut dedup = config
+        .dedup_window_secs
+        .map(|secs| Deduplicator::new(Duration::from_secs(secs)));
+
+    loop {
+        // while paused the event loop is not polled, so the broker holds on to
+        // unacknowledged QoS 1 messages instead of the bridge buffering them
+        if let Some(high_water) = high_water
+            && dispatcher.queued_points() >= high_water
+        {
+            warn!("Write queue reached {} points, pausing MQTT consumption", high_water);
+            while dispatcher.queued_points() > low_water {
+                tokio::select! {
+                    Some(e) = fatal_rx.recv() => return Err(e),
+                    _ = tokio::time::sleep(Duration::from_millis(100)) => {}
                 }
-                let data_point = builder.build()?;
-                client.write(bucket, tokio_stream::iter(vec![data_point])).await?;
             }
+            info!("Write queue drained to {} points, resuming MQTT consumption", low_water);
+        }
+
+        tokio::select! {
+            Some(e) = fatal_rx.recv() => return Err(e),
+            Some(message) = source_rx.recv() => {
+                if let Err(e) = dispatcher.dispatch(message).await {
+                    return Err(fatal_rx.try_recv().unwrap_or(e));
+                }
+            }
+            Some(event) = publishes.recv() => match event {
+                Ok(publish) if dedup.as_mut().is_some_and(|d| d.is_duplicate(&publish.topic, &publish.payload, publish.dup)) => {
+                    debug!("Suppressing duplicate message on {}", publish.topic);
+                }
+                Ok(publish) => {
+                    if let Err(e) = dispatcher.dispatch(Message::from(publish)).await {
+                        // a worker that stopped on a fatal error reports the underlying cause
+                        return Err(fatal_rx.try_recv().unwrap_or(e));
+                    }
+                }
+                Err(e) => return Err(e),
+            },
         }
-        Ok(())
     }
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Path to the configuration file
-    #[arg(short, long, default_value = "config.toml")]
-    config: String,
+async fn run_record(config: &Config, output: &str) -> Result<()> {
+    let mut recorder = Recorder::create(output)?;
+
+    let mut publishes = connect_brokers(config)?;
+
+    info!("Recording messages to {}", output);
+
+    let terminate_on_error = config.terminate_on_error.unwrap_or(false);
+
+    while let Some(event) = publishes.recv().await {
+        match event {
+            Ok(publish) => {
+                let message = RecordedMessage::from_publish(&publish, chrono::Utc::now());
+                debug!("Recording message on {} ({} bytes)", message.topic, publish.payload.len());
+                if let Err(e) = recorder.record(&message) {
+                    error!("Error recording message: {}", e);
+                    if terminate_on_error {
+                        return Err(e);
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
-    let config_content = fs::read_to_string(&args.config)
-        .map_err(|e| anyhow!("Failed to read config file {}: {}", args.config, e))?;
-    let config: Config = toml::from_str(&config_content)?;
+async fn run_replay(config: &Config, input: &str, realtime: bool, dry_run: bool, backfill: bool) -> Result<()> {
+    let pipeline = Pipeline::new(config)?;
+    let point_writers = point_writers(config, dry_run).await?;
 
-    let log_level = config.log_level.as_deref().unwrap_or("info");
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or(log_level));
+    let terminate_on_error = config.terminate_on_error.unwrap_or(false);
+    let mut previous: Option<chrono::DateTime<chrono::Utc>> = None;
+    let mut count = 0usize;
+
+    for message in RecordingReader::open(input)? {
+        let message = message?;
+
+        if realtime {
+            if let Some(delay) = previous.and_then(|p| (message.timestamp - p).to_std().ok()) {
+                tokio::time::sleep(delay).await;
+            }
+            previous = Some(message.timestamp);
+        }
 
-    let influx_client = InfluxClient::new(&config.influxdb);
+        let message = Message::from_recorded(&message, backfill)?;
+        if let Err(e) = extract_and_write(&pipeline, &message, &point_writers).await {
+            error!("Error processing message on {}: {}", message.topic, e);
+            if terminate_on_error {
+                return Err(e);
+            }
+        }
+        count += 1;
+    }
 
-    let mut mqttoptions = MqttOptions::new("mqtt_to_influx_bridge", &config.mqtt_host, config.mqtt_port);
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
+    info!("Replayed {} messages from {}", count, input);
+
+    Ok(())
+}
 
+async fn run_simulate(config: &Config, count: Option<u64>) -> Result<()> {
+    let simulators = config.simulate.clone().unwrap_or_default();
+    if simulators.is_empty() {
+        return Err(anyhow!("No [[simulate]] entries found in the configuration"));
+    }
+
+    // a separate client id keeps the simulator from kicking a running bridge off the broker
+    let mqttoptions = mqtt_options(config, "mqtt_to_influx_simulator")?;
     let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
-    client.subscribe(&config.mqtt_topic, QoS::AtLeastOnce).await?;
+    let mut backoff = Backoff::new(config);
+
+    info!("Simulating {} publishers against {}:{}", simulators.len(), config.mqtt_host, config.mqtt_port);
 
-    info!("Connected to MQTT and subscribed to {}", config.mqtt_topic);
+    let handles: Vec<_> = simulators
+        .into_iter()
+        .map(|simulator| tokio::spawn(simulator.run(client.clone(), count)))
+        .collect();
+    tokio::spawn(async move {
+        for handle in handles {
+            let _ = handle.await;
+        }
+        let _ = client.disconnect().await;
+    });
 
     let terminate_on_error = config.terminate_on_error.unwrap_or(false);
 
     loop {
         match eventloop.poll().await {
-            Ok(Event::Incoming(Packet::Publish(publish))) => {
-                if let Err(e) = process_message(&publish.payload, &config, &influx_client).await {
-                    error!("Error processing message: {}", e);
-                    if terminate_on_error {
-                        return Err(e);
-                    }
-                }
-            }
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+            Ok(Event::Incoming(Packet::ConnAck(_))) => backoff.reset(),
             Ok(_) => {}
             Err(e) => {
                 error!("Error in event loop: {}", e);
                 if terminate_on_error {
                     return Err(e.into());
                 }
-                tokio::time::sleep(Duration::from_secs(5)).await;
-            }
-        }
-    }
-}
-
-async fn process_message(payload: &[u8], config: &Config, influx_client: &InfluxClient) -> Result<()> {
-    let payload_str = std::str::from_utf8(payload)?;
-    let json: serde_json::Value = serde_json::from_str(payload_str)?;
-
-    for m_config in &config.measurements {
-        let finder = JsonPathFinder::from_str(&json.to_string(), &m_config.path)
-            .map_err(|e| anyhow!("Invalid JSONPath {}: {}", m_config.path, e))?;
-        
-        let found = finder.find();
-        
-        if let Some(val) = found.as_array().and_then(|a| a.first()) {
-            let mut float_val = if val.is_number() {
-                val.as_f64().unwrap_or(0.0)
-            } else if val.is_string() {
-                val.as_str().unwrap_or("0").parse::<f64>().unwrap_or(0.0)
-            } else {
-                continue;
-            };
-
-            if let Some(expr) = &m_config.expression {
-                let mut context = HashMapContext::new();
-                context.set_value("value".into(), Value::Float(float_val))?;
-                if let Ok(eval_res) = eval_with_context_mut(expr, &mut context) {
-                    if let Ok(f) = eval_res.as_float() {
-                        float_val = f;
-                    } else if let Ok(i) = eval_res.as_int() {
-                        float_val = i as f64;
-                    }
-                }
+                tokio::time::sleep(backoff.next_delay()).await;
             }
-
-            debug!("Writing measurement: {} = {}", m_config.name, float_val);
-            influx_client.write(&m_config.name, float_val, &config.influxdb.bucket, &m_config.tags).await?;
         }
     }
 
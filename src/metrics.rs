@@ -0,0 +1,92 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+
+/// Process wide gauges and counters, served on the admin `/metrics` endpoint.
+///
+/// They are updated where the work happens rather than threaded through every
+/// task, the same values apply whichever dispatch mode is running.
+pub struct Metrics {
+    /// Messages waiting for a worker or topic pipeline
+    pub queued_messages: AtomicUsize,
+    /// Points waiting for a writer
+    pub queued_points: AtomicUsize,
+    /// Approximate memory used by the waiting points
+    pub queued_bytes: AtomicUsize,
+    /// Point capacity of the write buffer, so alerts can be set on the fill ratio
+    pub queue_capacity_points: AtomicUsize,
+    /// Requests to InfluxDB that have been sent but not answered yet
+    pub in_flight_writes: AtomicUsize,
+    /// Points discarded because the write buffer was full or they expired in the disk buffer
+    pub dropped_points: AtomicU64,
+    /// Messages discarded because a topic pipeline was full
+    pub dropped_messages: AtomicU64,
+    /// Points skipped because the `filter` of their measurement was false
+    pub filtered_points: AtomicU64,
+    /// Readings dropped for being outside the `min`, `max` or `max_step` of their measurement
+    pub rejected_readings: AtomicU64,
+    /// Size of the points buffered on disk waiting to be replayed
+    pub disk_buffer_bytes: AtomicU64,
+    /// Unix time in milliseconds of the oldest point buffered on disk, 0 when there is none
+    pub disk_buffer_oldest_ms: AtomicI64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    queued_messages: AtomicUsize::new(0),
+    queued_points: AtomicUsize::new(0),
+    queued_bytes: AtomicUsize::new(0),
+    queue_capacity_points: AtomicUsize::new(0),
+    in_flight_writes: AtomicUsize::new(0),
+    dropped_points: AtomicU64::new(0),
+    dropped_messages: AtomicU64::new(0),
+    filtered_points: AtomicU64::new(0),
+    rejected_readings: AtomicU64::new(0),
+    disk_buffer_bytes: AtomicU64::new(0),
+    disk_buffer_oldest_ms: AtomicI64::new(0),
+};
+
+/// Counts a write as in flight until it is dropped, including when the
+/// writing future is cancelled.
+pub struct InFlightWrite;
+
+impl InFlightWrite {
+    pub fn start() -> Self {
+        METRICS.in_flight_writes.fetch_add(1, Ordering::Relaxed);
+        InFlightWrite
+    }
+}
+
+impl Drop for InFlightWrite {
+    fn drop(&mut self) {
+        METRICS.in_flight_writes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Formats every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP mqtt_to_influx_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE mqtt_to_influx_{} {}", name, kind);
+            let _ = writeln!(out, "mqtt_to_influx_{} {}", name, value);
+        };
+        let load = |value: &AtomicUsize| value.load(Ordering::Relaxed) as u64;
+        metric("queued_messages", "gauge", "Messages waiting to be processed", load(&self.queued_messages));
+        metric("queued_points", "gauge", "Points waiting to be written", load(&self.queued_points));
+        metric("queued_bytes", "gauge", "Approximate memory used by points waiting to be written", load(&self.queued_bytes));
+        metric("queue_capacity_points", "gauge", "Maximum number of points buffered in memory", load(&self.queue_capacity_points));
+        metric("in_flight_writes", "gauge", "Writes sent to InfluxDB and not yet answered", load(&self.in_flight_writes));
+        metric("dropped_points_total", "counter", "Points dropped because the write buffer was full or they expired in the disk buffer", self.dropped_points.load(Ordering::Relaxed));
+        metric("dropped_messages_total", "counter", "Messages dropped because a topic pipeline was full", self.dropped_messages.load(Ordering::Relaxed));
+        metric("filtered_points_total", "counter", "Points skipped by the filter of their measurement", self.filtered_points.load(Ordering::Relaxed));
+        metric("rejected_readings_total", "counter", "Readings dropped for being outside the bounds of their measurement", self.rejected_readings.load(Ordering::Relaxed));
+        metric("disk_buffer_bytes", "gauge", "Size of the points buffered on disk", self.disk_buffer_bytes.load(Ordering::Relaxed));
+        let oldest = self.disk_buffer_oldest_ms.load(Ordering::Relaxed);
+        let age = match oldest {
+            0 => 0,
+            oldest => (chrono::Utc::now().timestamp_millis() - oldest).max(0) as u64 / 1000,
+        };
+        metric("disk_buffer_oldest_age_seconds", "gauge", "Age of the oldest point buffered on disk", age);
+        out
+    }
+}
@@ -0,0 +1,493 @@
+use anyhow::{anyhow, Result};
+use log::{error, warn};
+use serde::Deserialize;
+use rumqttc::tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rumqttc::tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rumqttc::tokio_rustls::rustls::{self, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, Packet, Publish, QoS, TlsConfiguration, Transport, v5};
+use std::fs;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Config, TopicConfig, mqtt_options};
+
+/// What polling a broker connection produced.
+pub enum MqttEvent {
+    Publish(Publish),
+    /// The broker accepted a connection, which after a reconnect may have
+    /// started a new session without the earlier subscriptions
+    Connected { session_present: bool },
+    Other,
+}
+
+/// A broker connection speaking either MQTT 3.1.1 or MQTT 5. The event loops
+/// are boxed as their sizes differ widely.
+pub enum MqttConnection {
+    V3(AsyncClient, Box<EventLoop>),
+    V5(v5::AsyncClient, Box<v5::EventLoop>),
+}
+
+impl MqttConnection {
+    pub fn new(config: &Config, client_id: &str) -> Result<Self> {
+        match config.mqtt_version.unwrap_or(3) {
+            3 => {
+                if config.mqtt_receive_maximum.is_some() || config.mqtt_topic_alias_maximum.is_some() {
+                    warn!("mqtt_receive_maximum and mqtt_topic_alias_maximum require mqtt_version = 5, ignoring them");
+                }
+                let mut mqttoptions = mqtt_options(config, client_id)?;
+                if let Some(status) = status(config) {
+                    mqttoptions.set_last_will(LastWill::new(status.topic, status.offline, QoS::AtLeastOnce, true));
+                }
+                let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
+                Ok(MqttConnection::V3(client, Box::new(eventloop)))
+            }
+            5 => {
+                let mut mqttoptions = v5::MqttOptions::new(client_id, broker_address(config), config.mqtt_port);
+                mqttoptions.set_keep_alive(keep_alive(config)?);
+                mqttoptions.set_transport(transport(config)?);
+                if let Some(inflight) = inflight(config)? {
+                    mqttoptions.set_outgoing_inflight_upper_limit(inflight);
+                }
+                if let Some(max_packet_size) = config.mqtt_max_packet_size {
+                    mqttoptions.set_max_packet_size(Some(max_packet_size));
+                }
+                if let Some((username, password)) = credentials(config) {
+                    mqttoptions.set_credentials(username, password);
+                }
+                // the broker stops sending once this many QoS 1/2 messages are unacknowledged
+                mqttoptions.set_receive_maximum(config.mqtt_receive_maximum);
+                mqttoptions.set_topic_alias_max(config.mqtt_topic_alias_maximum);
+                let clean = clean_session(config)?;
+                mqttoptions.set_clean_start(clean);
+                if !clean {
+                    // MQTT 5 sessions end with the connection unless given an expiry
+                    let mut properties = mqttoptions.connect_properties().unwrap_or_default();
+                    properties.session_expiry_interval = Some(config.mqtt_session_expiry_secs.unwrap_or(86400));
+                    mqttoptions.set_connect_properties(properties);
+                }
+                if let Some(status) = status(config) {
+                    mqttoptions.set_last_will(v5::mqttbytes::v5::LastWill::new(
+                        status.topic,
+                        status.offline,
+                        v5::mqttbytes::QoS::AtLeastOnce,
+                        true,
+                        None,
+                    ));
+                }
+                let (client, eventloop) = v5::AsyncClient::new(mqttoptions, 10);
+                Ok(MqttConnection::V5(client, Box::new(eventloop)))
+            }
+            version => Err(anyhow!("Unsupported MQTT version: {}", version)),
+        }
+    }
+
+    /// Subscribes to every topic from a separate task, as the requests only
+    /// leave once the event loop is polled and could otherwise fill its queue.
+    pub fn subscribe_all(&self, topics: &[(String, QoS)]) {
+        let topics = topics.to_vec();
+        match self {
+            MqttConnection::V3(client, _) => {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    for (topic, qos) in topics {
+                        if let Err(e) = client.subscribe(&topic, qos).await {
+                            error!("Failed to subscribe to {}: {}", topic, e);
+                        }
+                    }
+                });
+            }
+            MqttConnection::V5(client, _) => {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    for (topic, qos) in topics {
+                        if let Err(e) = client.subscribe(&topic, v5_qos(qos)).await {
+                            error!("Failed to subscribe to {}: {}", topic, e);
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    /// Publishes a retained message from a separate task, for the same reason
+    /// as [`MqttConnection::subscribe_all`].
+    pub fn publish_retained(&self, topic: &str, payload: &str) {
+        let topic = topic.to_string();
+        let payload = payload.to_string();
+        match self {
+            MqttConnection::V3(client, _) => {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = client.publish(&topic, QoS::AtLeastOnce, true, payload).await {
+                        error!("Failed to publish to {}: {}", topic, e);
+                    }
+                });
+            }
+            MqttConnection::V5(client, _) => {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = client.publish(&topic, v5::mqttbytes::QoS::AtLeastOnce, true, payload).await {
+                        error!("Failed to publish to {}: {}", topic, e);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Drives the connection, reconnecting after an error on the next call.
+    /// MQTT 5 publishes are converted, with topic aliases already resolved.
+    pub async fn poll(&mut self) -> Result<MqttEvent> {
+        match self {
+            MqttConnection::V3(_, eventloop) => match eventloop.poll().await? {
+                Event::Incoming(Packet::Publish(publish)) => Ok(MqttEvent::Publish(publish)),
+                Event::Incoming(Packet::ConnAck(connack)) => Ok(MqttEvent::Connected {
+                    session_present: connack.session_present,
+                }),
+                _ => Ok(MqttEvent::Other),
+            },
+            MqttConnection::V5(_, eventloop) => match eventloop.poll().await? {
+                v5::Event::Incoming(v5::Incoming::Publish(publish)) => {
+                    let topic = String::from_utf8(publish.topic.to_vec())
+                        .map_err(|e| anyhow!("Failed to decode MQTT 5 topic: {}", e))?;
+                    let qos = match publish.qos {
+                        v5::mqttbytes::QoS::AtMostOnce => QoS::AtMostOnce,
+                        v5::mqttbytes::QoS::AtLeastOnce => QoS::AtLeastOnce,
+                        v5::mqttbytes::QoS::ExactlyOnce => QoS::ExactlyOnce,
+                    };
+                    let mut converted = Publish::from_bytes(topic, qos, publish.payload);
+                    converted.retain = publish.retain;
+                    converted.dup = publish.dup;
+                    Ok(MqttEvent::Publish(converted))
+                }
+                v5::Event::Incoming(v5::Incoming::ConnAck(connack)) => Ok(MqttEvent::Connected {
+                    session_present: connack.session_present,
+                }),
+                _ => Ok(MqttEvent::Other),
+            },
+        }
+    }
+}
+
+fn v5_qos(qos: QoS) -> v5::mqttbytes::QoS {
+    match qos {
+        QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+        QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
+
+/// How often the connection is checked with a ping when idle. MQTT 3.1.1
+/// allows `0` to disable keep alive, MQTT 5 in this client needs at least 5
+/// seconds.
+pub fn keep_alive(config: &Config) -> Result<Duration> {
+    let secs = config.mqtt_keep_alive_secs.unwrap_or(5);
+    if config.mqtt_version == Some(5) && secs < 5 {
+        return Err(anyhow!("mqtt_keep_alive_secs must be at least 5 with mqtt_version = 5"));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// The limit on QoS 1 and 2 publishes sent while waiting for acknowledgements.
+pub fn inflight(config: &Config) -> Result<Option<u16>> {
+    match config.mqtt_inflight {
+        Some(0) => Err(anyhow!("mqtt_inflight must be at least 1")),
+        inflight => Ok(inflight),
+    }
+}
+
+/// The retained messages announcing whether the bridge is running.
+pub struct Status {
+    pub topic: String,
+    /// Published on every connect
+    pub online: String,
+    /// Registered as the last will, which the broker publishes when the
+    /// connection drops without a disconnect
+    pub offline: String,
+}
+
+/// The bridge status messages, when `mqtt_status_topic` is set.
+pub fn status(config: &Config) -> Option<Status> {
+    Some(Status {
+        topic: config.mqtt_status_topic.clone()?,
+        online: config.mqtt_status_online.clone().unwrap_or_else(|| "online".to_string()),
+        offline: config.mqtt_status_offline.clone().unwrap_or_else(|| "offline".to_string()),
+    })
+}
+
+/// The delay before reconnecting to a broker or retrying a write, doubling
+/// after every failed attempt up to a maximum and starting over once an
+/// attempt succeeds.
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    jitter: bool,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(config: &Config) -> Self {
+        let initial = Duration::from_millis(config.mqtt_reconnect_initial_ms.unwrap_or(1000));
+        let max = Duration::from_millis(config.mqtt_reconnect_max_ms.unwrap_or(60000));
+        Backoff::with_limits(initial, max, config.mqtt_reconnect_jitter.unwrap_or(true))
+    }
+
+    pub fn with_limits(initial: Duration, max: Duration, jitter: bool) -> Self {
+        let initial = initial.max(Duration::from_millis(1));
+        Backoff {
+            initial,
+            max: max.max(initial),
+            jitter,
+            current: initial,
+        }
+    }
+
+    /// The delay before the next attempt. With jitter it is somewhere between
+    /// half and all of the backoff, so bridges cut off by the same broker
+    /// restart don't all reconnect at once.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = if self.jitter {
+            self.current.mul_f64(rand::random_range(0.5..=1.0))
+        } else {
+            self.current
+        };
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// The configured client ID, with a random suffix when `mqtt_client_id_suffix`
+/// is set so several instances can share one config.
+pub fn client_id(config: &Config) -> String {
+    let client_id = config.mqtt_client_id.as_deref().unwrap_or("mqtt_to_influx_bridge");
+    if config.mqtt_client_id_suffix.unwrap_or(false) {
+        format!("{}_{:08x}", client_id, rand::random::<u32>())
+    } else {
+        client_id.to_string()
+    }
+}
+
+/// Whether the broker discards the session on connect. A persistent session
+/// needs a stable client ID, or the queued messages are never collected.
+pub fn clean_session(config: &Config) -> Result<bool> {
+    let clean = config.mqtt_clean_session.unwrap_or(true);
+    if !clean {
+        if config.mqtt_client_id.as_deref() == Some("") {
+            return Err(anyhow!("mqtt_clean_session = false requires a client ID"));
+        }
+        if config.mqtt_client_id_suffix.unwrap_or(false) {
+            warn!("mqtt_client_id_suffix gives every start a new session, so queued messages are not delivered");
+        }
+    }
+    Ok(clean)
+}
+
+/// The broker username and password from the config, falling back to the
+/// `MQTT_USERNAME` and `MQTT_PASSWORD` environment variables so secrets can be
+/// kept out of the config file.
+pub fn credentials(config: &Config) -> Option<(String, String)> {
+    let username = config
+        .mqtt_username
+        .clone()
+        .or_else(|| std::env::var("MQTT_USERNAME").ok())?;
+    let password = config
+        .mqtt_password
+        .clone()
+        .or_else(|| std::env::var("MQTT_PASSWORD").ok())
+        .unwrap_or_default();
+    Some((username, password))
+}
+
+/// A further broker connected to alongside the one configured at the top
+/// level. Settings left out are taken from the top-level configuration.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BrokerConfig {
+    /// Used in log messages, `host:port` by default
+    name: Option<String>,
+    mqtt_host: String,
+    mqtt_port: Option<u16>,
+    mqtt_topic: Option<String>,
+    mqtt_topics: Option<Vec<TopicConfig>>,
+    mqtt_version: Option<u8>,
+    mqtt_transport: Option<MqttTransport>,
+    mqtt_ws_path: Option<String>,
+    mqtt_client_id: Option<String>,
+    mqtt_username: Option<String>,
+    mqtt_password: Option<String>,
+    mqtt_tls: Option<bool>,
+    mqtt_ca_cert: Option<String>,
+    mqtt_tls_skip_verify: Option<bool>,
+    mqtt_client_cert: Option<String>,
+    mqtt_client_key: Option<String>,
+}
+
+/// Every broker to connect to with its name, each as a copy of the config
+/// carrying that broker's settings.
+pub fn brokers(config: &Config) -> Vec<(String, Config)> {
+    let mut brokers = vec![(format!("{}:{}", config.mqtt_host, config.mqtt_port), config.clone())];
+    for broker in config.brokers.iter().flatten() {
+        let mut merged = config.clone();
+        merged.mqtt_host = broker.mqtt_host.clone();
+        merged.mqtt_port = broker.mqtt_port.unwrap_or(config.mqtt_port);
+        if broker.mqtt_topic.is_some() || broker.mqtt_topics.is_some() {
+            merged.mqtt_topic = broker.mqtt_topic.clone();
+            merged.mqtt_topics = broker.mqtt_topics.clone();
+        }
+        merged.mqtt_version = broker.mqtt_version.or(config.mqtt_version);
+        merged.mqtt_transport = broker.mqtt_transport.or(config.mqtt_transport);
+        merged.mqtt_ws_path = broker.mqtt_ws_path.clone().or(merged.mqtt_ws_path);
+        merged.mqtt_client_id = broker.mqtt_client_id.clone().or(merged.mqtt_client_id);
+        merged.mqtt_username = broker.mqtt_username.clone().or(merged.mqtt_username);
+        merged.mqtt_password = broker.mqtt_password.clone().or(merged.mqtt_password);
+        merged.mqtt_tls = broker.mqtt_tls.or(config.mqtt_tls);
+        merged.mqtt_ca_cert = broker.mqtt_ca_cert.clone().or(merged.mqtt_ca_cert);
+        merged.mqtt_tls_skip_verify = broker.mqtt_tls_skip_verify.or(config.mqtt_tls_skip_verify);
+        merged.mqtt_client_cert = broker.mqtt_client_cert.clone().or(merged.mqtt_client_cert);
+        merged.mqtt_client_key = broker.mqtt_client_key.clone().or(merged.mqtt_client_key);
+        let name = broker
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("{}:{}", merged.mqtt_host, merged.mqtt_port));
+        brokers.push((name, merged));
+    }
+    brokers
+}
+
+/// How the bridge reaches the broker.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MqttTransport {
+    /// A plain or, with `mqtt_tls`, TLS connection
+    #[default]
+    Tcp,
+    /// WebSockets
+    Ws,
+    /// WebSockets over TLS
+    Wss,
+}
+
+/// The broker host, or for WebSockets the URL of its endpoint.
+pub fn broker_address(config: &Config) -> String {
+    let scheme = match config.mqtt_transport.unwrap_or_default() {
+        MqttTransport::Tcp => return config.mqtt_host.clone(),
+        MqttTransport::Ws => "ws",
+        MqttTransport::Wss => "wss",
+    };
+    let path = config.mqtt_ws_path.as_deref().unwrap_or("/mqtt");
+    format!("{}://{}:{}/{}", scheme, config.mqtt_host, config.mqtt_port, path.trim_start_matches('/'))
+}
+
+pub fn transport(config: &Config) -> Result<Transport> {
+    match config.mqtt_transport.unwrap_or_default() {
+        MqttTransport::Tcp if config.mqtt_tls.unwrap_or(false) => {
+            Ok(Transport::tls_with_config(tls_configuration(config)?))
+        }
+        MqttTransport::Tcp => Ok(Transport::Tcp),
+        MqttTransport::Ws => Ok(Transport::Ws),
+        MqttTransport::Wss => Ok(Transport::wss_with_config(tls_configuration(config)?)),
+    }
+}
+
+/// TLS verified against `mqtt_ca_cert` or the system's trusted certificates,
+/// authenticating with a client certificate when one is set.
+fn tls_configuration(config: &Config) -> Result<TlsConfiguration> {
+    let builder = ClientConfig::builder();
+    let builder = if config.mqtt_tls_skip_verify.unwrap_or(false) {
+        warn!("Broker certificate verification is disabled");
+        builder.dangerous().with_custom_certificate_verifier(Arc::new(SkipVerification))
+    } else {
+        builder.with_root_certificates(root_certificates(config)?)
+    };
+    let tls_config = match (&config.mqtt_client_cert, &config.mqtt_client_key) {
+        (Some(cert), Some(key)) => {
+            let chain = read_certificates(cert, "client certificate")?;
+            let file = fs::File::open(key).map_err(|e| anyhow!("Failed to open client key {}: {}", key, e))?;
+            let private_key = rustls_pemfile::private_key(&mut BufReader::new(file))
+                .map_err(|e| anyhow!("Failed to read client key {}: {}", key, e))?
+                .ok_or_else(|| anyhow!("No private key found in client key {}", key))?;
+            builder
+                .with_client_auth_cert(chain, private_key)
+                .map_err(|e| anyhow!("Invalid client certificate {}: {}", cert, e))?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(anyhow!("mqtt_client_cert and mqtt_client_key must be set together")),
+    };
+    Ok(TlsConfiguration::Rustls(Arc::new(tls_config)))
+}
+
+/// Reads every certificate in a PEM file, failing when there are none.
+fn read_certificates(path: &str, what: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let file = fs::File::open(path).map_err(|e| anyhow!("Failed to open {} {}: {}", what, path, e))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Failed to read {} {}: {}", what, path, e))?;
+    if certificates.is_empty() {
+        return Err(anyhow!("No certificates found in {} {}", what, path));
+    }
+    Ok(certificates)
+}
+
+fn root_certificates(config: &Config) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match &config.mqtt_ca_cert {
+        Some(path) => {
+            roots.add_parsable_certificates(read_certificates(path, "CA certificate")?);
+            if roots.is_empty() {
+                return Err(anyhow!("No usable certificates found in CA certificate {}", path));
+            }
+        }
+        None => {
+            let certificates = rustls_native_certs::load_native_certs()
+                .map_err(|e| anyhow!("Failed to load the system's trusted certificates: {}", e))?;
+            roots.add_parsable_certificates(certificates);
+        }
+    }
+    Ok(roots)
+}
+
+/// Accepts any broker certificate, for self-signed test brokers.
+#[derive(Debug)]
+struct SkipVerification;
+
+impl ServerCertVerifier for SkipVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = rustls::crypto::ring::default_provider().signature_verification_algorithms;
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = rustls::crypto::ring::default_provider().signature_verification_algorithms;
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Result};
+use jsonpath_rust::JsonPathInst;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// A piece of a template.
+enum Part {
+    Literal(String),
+    /// `{topic}`, the whole publish topic
+    Topic,
+    /// `{topic[N]}`, a single level of the publish topic counting from zero
+    TopicLevel(usize),
+    /// `{$.path}` or a bare `$.path` name, a value from the payload
+    Path(JsonPathInst),
+}
+
+/// A measurement name or tag value derived per message from the topic and
+/// payload, such as `{topic[1]}_temperature` or `$.device.type`.
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+impl NameTemplate {
+    /// Parses a template, `what` describing it in errors, as in `measurement name temp`.
+    pub fn parse(name: &str, what: &str) -> Result<Self> {
+        let path = |path: &str| {
+            JsonPathInst::from_str(path).map_err(|e| anyhow!("Invalid JSONPath {} in {}: {}", path, what, e))
+        };
+        if name.starts_with('$') {
+            return Ok(NameTemplate { parts: vec![Part::Path(path(name)?)] });
+        }
+        let mut parts = Vec::new();
+        let mut rest = name;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| anyhow!("Unclosed placeholder in {}", what))?;
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let placeholder = &rest[start + 1..end];
+            parts.push(if placeholder == "topic" {
+                Part::Topic
+            } else if let Some(level) = placeholder.strip_prefix("topic[").and_then(|level| level.strip_suffix(']')) {
+                Part::TopicLevel(
+                    level.parse().map_err(|_| anyhow!("Invalid topic level {} in {}", level, what))?,
+                )
+            } else if placeholder.starts_with('$') {
+                Part::Path(path(placeholder)?)
+            } else {
+                return Err(anyhow!("Unknown placeholder {{{}}} in {}", placeholder, what));
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(NameTemplate { parts })
+    }
+
+    /// Whether the name is the same for every message.
+    pub fn is_fixed(&self) -> bool {
+        self.parts.iter().all(|part| matches!(part, Part::Literal(_)))
+    }
+
+    /// Fills in the template for a message, or `None` when the topic lacks a
+    /// level or the payload lacks a value the template refers to.
+    pub fn render(&self, topic: &str, json: &Value) -> Option<String> {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => name.push_str(text),
+                Part::Topic => name.push_str(topic),
+                Part::TopicLevel(level) => name.push_str(topic.split('/').nth(*level)?),
+                Part::Path(path) => match &*path.find_slice(json).into_iter().next()? {
+                    Value::String(text) => name.push_str(text),
+                    Value::Number(number) => name.push_str(&number.to_string()),
+                    Value::Bool(flag) => name.push_str(&flag.to_string()),
+                    _ => return None,
+                },
+            }
+        }
+        (!name.is_empty()).then_some(name)
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+use evalexpr::{Context, ContextWithMutableVariables, EvalexprResult, HashMapContext, Value};
+use std::collections::HashMap;
+
+use crate::state::STATE;
+
+/// Converts the initial values of a measurement's `variables`, which keep
+/// their type, so a running total needs a float such as `0.0`.
+pub fn declare(variables: Option<&HashMap<String, serde_json::Value>>, what: &str) -> Result<Vec<(String, Value)>> {
+    let mut declared = variables
+        .into_iter()
+        .flatten()
+        .map(|(name, initial)| {
+            let value = from_json(initial)
+                .ok_or_else(|| anyhow!("Variable {} of {} must be a number, string or boolean", name, what))?;
+            Ok((name.clone(), value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    declared.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(declared)
+}
+
+/// The variables an expression keeps for one series from one message to the
+/// next, such as a running total or a smoothed value.
+pub struct Persistent {
+    key: String,
+    values: Vec<(String, Value)>,
+}
+
+impl Persistent {
+    /// The values saved for the series `key`, or the initial ones of those
+    /// not saved yet or saved with another type.
+    pub fn load(key: String, declared: &[(String, Value)]) -> Self {
+        let saved = STATE.update("variables", &key, |saved| saved.clone());
+        let values = declared
+            .iter()
+            .map(|(name, initial)| {
+                let value = saved
+                    .as_ref()
+                    .and_then(|saved| saved.get(name))
+                    .and_then(from_json)
+                    .filter(|value| std::mem::discriminant(value) == std::mem::discriminant(initial))
+                    .unwrap_or_else(|| initial.clone());
+                (name.clone(), value)
+            })
+            .collect();
+        Persistent { key, values }
+    }
+
+    pub fn install(&self, context: &mut HashMapContext) -> EvalexprResult<()> {
+        for (name, value) in &self.values {
+            context.set_value(name.clone(), value.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Saves the values the expression left in the context.
+    pub fn save(self, context: &HashMapContext) {
+        let values: serde_json::Map<String, serde_json::Value> = self
+            .values
+            .into_iter()
+            .map(|(name, value)| {
+                let value = context.get_value(&name).cloned().unwrap_or(value);
+                (name, to_json(&value))
+            })
+            .collect();
+        STATE.update("variables", &self.key, |saved| *saved = Some(values.into()));
+    }
+}
+
+fn from_json(value: &serde_json::Value) -> Option<Value> {
+    match value {
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) if !number.is_f64() => Some(Value::Int(integer)),
+            _ => number.as_f64().map(Value::Float),
+        },
+        serde_json::Value::String(text) => Some(Value::String(text.clone())),
+        serde_json::Value::Bool(flag) => Some(Value::Boolean(*flag)),
+        _ => None,
+    }
+}
+
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Int(integer) => (*integer).into(),
+        Value::Float(float) => serde_json::Number::from_f64(*float).map_or(serde_json::Value::Null, Into::into),
+        Value::String(text) => text.as_str().into(),
+        Value::Boolean(flag) => (*flag).into(),
+        _ => serde_json::Value::Null,
+    }
+}
@@ -0,0 +1,1382 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use evalexpr::{build_operator_tree, ContextWithMutableVariables, HashMapContext, Node, Value};
+use jsonpath_rust::{JsonPathInst, JsonPtr};
+use prost_reflect::MessageDescriptor;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use crate::binary::BinaryField;
+use crate::bounds::Bounds;
+use crate::compression::{self, Compression};
+use crate::counters::{self, Counter};
+use crate::decoder::{self, Decoder};
+use crate::delimited::Column;
+use crate::field_types::FieldTypes;
+use crate::geo;
+use crate::global_tags::GlobalTags;
+use crate::metrics::METRICS;
+use crate::name_template::NameTemplate;
+use crate::persistent::{self, Persistent};
+use crate::presets::{self, Preset};
+use crate::protobuf;
+use crate::record::RecordedMessage;
+use crate::sanitize::Sanitizer;
+use crate::script::Script;
+use crate::sink::{self, SinkRoutes};
+use crate::state::STATE;
+use crate::topic_filter;
+use crate::units;
+use crate::timestamp::{parse_timestamp, parse_timezone, TimestampFormat};
+use crate::{Config, FieldConfig, MeasurementConfig};
+
+/// A message entering the pipeline, either from the broker or a recording.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Bytes,
+    /// The QoS the message was delivered with, 0 for sources other than MQTT
+    pub qos: u8,
+    pub retain: bool,
+    /// When the message arrived, which every point extracted from it is stamped with
+    pub received_at: DateTime<Utc>,
+}
+
+impl From<rumqttc::Publish> for Message {
+    fn from(publish: rumqttc::Publish) -> Self {
+        Message {
+            topic: publish.topic,
+            payload: publish.payload,
+            qos: publish.qos as u8,
+            retain: publish.retain,
+            received_at: Utc::now(),
+        }
+    }
+}
+
+impl Message {
+    /// Converts a recorded message, keeping its original receipt time when
+    /// backfilling and otherwise treating it as received now.
+    pub fn from_recorded(recorded: &RecordedMessage, backfill: bool) -> Result<Self> {
+        Ok(Message {
+            topic: recorded.topic.clone(),
+            payload: Bytes::from(recorded.payload_bytes()?),
+            qos: recorded.qos,
+            retain: recorded.retain,
+            received_at: if backfill { recorded.timestamp } else { Utc::now() },
+        })
+    }
+
+    /// Variables describing the message for expressions: the `topic`, its
+    /// levels as `topic_levels` and `topic_0`, `topic_1` and so on,
+    /// `retain`, `qos` and `payload_size` in bytes.
+    fn variables(&self) -> Vec<(String, Value)> {
+        let levels: Vec<&str> = self.topic.split('/').collect();
+        let mut variables = vec![
+            ("topic".to_string(), Value::String(self.topic.clone())),
+            ("topic_levels".to_string(), Value::Tuple(levels.iter().map(|level| Value::from(*level)).collect())),
+            ("retain".to_string(), Value::Boolean(self.retain)),
+            ("qos".to_string(), Value::Int(i64::from(self.qos))),
+            ("payload_size".to_string(), Value::Int(self.payload.len() as i64)),
+        ];
+        for (index, level) in levels.iter().enumerate() {
+            variables.push((format!("topic_{}", index), Value::from(*level)));
+        }
+        variables
+    }
+}
+
+/// The value of a single field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    UInteger(u64),
+    Boolean(bool),
+    /// Text written by event measurements
+    String(String),
+}
+
+/// The InfluxDB type numeric values are written as. A field keeps the type of
+/// the first value written to it, so counters written as floats conflict with
+/// a schema holding integers.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    #[default]
+    Float,
+    /// A signed 64-bit integer
+    Int,
+    /// An unsigned 64-bit integer
+    Uint,
+    /// `true` or `false`, non-zero numbers being true
+    Bool,
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        FieldValue::Float(value)
+    }
+}
+
+/// How a measurement reads the message payload.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    /// A JSON document the measurement's path is evaluated against
+    #[default]
+    Json,
+    /// The whole payload as a single number, or as text when it is not one
+    Raw,
+    /// A MessagePack document, evaluated with JSONPath like JSON
+    Msgpack,
+    /// A CBOR document, evaluated with JSONPath like JSON
+    Cbor,
+    /// A binary protobuf message of the type named by `protobuf_message`
+    Protobuf,
+    /// An XML document, converted to JSON for JSONPath
+    Xml,
+    /// Delimited lines such as `1699999999,23.4,56.1`, read with `column`
+    Csv,
+    /// A binary structure the measurement reads a value from at its `offset`
+    Binary,
+}
+
+/// A format together with the protobuf message type, an index into the
+/// message types `Pipeline::new` found, for protobuf payloads, or the
+/// measurement reading the payload, an index into `Pipeline::measurements`,
+/// for binary payloads.
+type Decoding = (PayloadFormat, Option<usize>);
+
+/// A message payload decoded in every format the measurements use. A format
+/// the payload could not be decoded in holds the error instead.
+pub struct Documents {
+    documents: Vec<(Decoding, Result<serde_json::Value, String>)>,
+}
+
+impl Documents {
+    fn get(&self, decoding: Decoding) -> Option<&Result<serde_json::Value, String>> {
+        self.documents.iter().find(|(d, _)| *d == decoding).map(|(_, document)| document)
+    }
+}
+
+/// A point ready to be written to InfluxDB.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Point {
+    pub measurement: String,
+    /// Field names and values, a single `value` field for configured measurements
+    pub fields: Vec<(String, FieldValue)>,
+    pub tags: Option<HashMap<String, String>>,
+    /// The bucket, or database for InfluxDB 1.x, written to instead of `influxdb.bucket`
+    pub bucket: Option<String>,
+    /// The sinks the point is routed to, all of them when None. Not kept in
+    /// disk buffers and dead letters, which belong to a single sink.
+    #[serde(skip)]
+    pub sinks: Option<Arc<[String]>>,
+    /// Assigned once when the point is extracted. Anything retrying a write must
+    /// send the point unchanged so InfluxDB overwrites rather than duplicates it.
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Point {
+    /// Rough number of bytes the point occupies in memory, for buffer limits.
+    pub fn approximate_size(&self) -> usize {
+        let tags = self
+            .tags
+            .as_ref()
+            .map(|tags| tags.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>())
+            .unwrap_or(0);
+        let fields = self
+            .fields
+            .iter()
+            .map(|(name, value)| match value {
+                FieldValue::Float(_) | FieldValue::Integer(_) | FieldValue::UInteger(_) | FieldValue::Boolean(_) => {
+                    name.len()
+                }
+                FieldValue::String(text) => name.len() + text.len(),
+            })
+            .sum::<usize>();
+        std::mem::size_of::<Point>() + self.measurement.len() + tags + fields
+            + self.fields.len() * std::mem::size_of::<(String, FieldValue)>()
+    }
+
+    /// Identifies the series the point belongs to: its measurement and tag set.
+    pub fn series_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.measurement.hash(&mut hasher);
+        if let Some(tags) = &self.tags {
+            let mut sorted: Vec<_> = tags.iter().collect();
+            sorted.sort();
+            sorted.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+/// A field of a measurement writing several, compiled ahead of time.
+struct PreparedField {
+    name: String,
+    path: JsonPathInst,
+    expression: Option<Node>,
+    field_type: Option<FieldType>,
+    /// The field's own bounds, or those of the measurement
+    bounds: Bounds,
+}
+
+/// What an expression is evaluated with besides `value`.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    variables: &'a [(String, Value)],
+    /// The measurement and tags of the point, set when an expression remembers values
+    series: Option<&'a str>,
+    field: &'a str,
+    timestamp: DateTime<Utc>,
+    /// Whether the expressions use `delta` or `rate`
+    counters: bool,
+    /// The initial values of the variables kept between messages
+    persistent: &'a [(String, Value)],
+}
+
+/// What the points a script returns are given unless they set their own.
+struct ScriptDefaults<'a> {
+    /// The measurement whose script it is, None for the script of a topic
+    measurement: Option<&'a PreparedMeasurement>,
+    name: Option<&'a str>,
+    tags: Option<HashMap<String, String>>,
+    timestamp: DateTime<Utc>,
+}
+
+/// A measurement with its JSONPath and expression compiled ahead of time.
+struct PreparedMeasurement {
+    config: MeasurementConfig,
+    /// Index of the measurement's `protobuf_message` among the message types
+    protobuf_message: Option<usize>,
+    /// Set when the name is derived from the topic or payload
+    name: Option<NameTemplate>,
+    /// The sinks named by `sinks`, checked against those configured
+    sinks: Option<Arc<[String]>>,
+    /// Tags derived from the topic or payload, the fixed ones staying in `config`
+    tag_templates: Vec<(String, NameTemplate)>,
+    path: JsonPathInst,
+    /// The fields written instead of a single value from `path`, by name
+    fields: Vec<PreparedField>,
+    /// Lower-cased strings read as true and false
+    true_values: Vec<String>,
+    false_values: Vec<String>,
+    /// The `map` with lower-cased keys
+    map: HashMap<String, serde_json::Value>,
+    /// Elements of the payload the other paths are evaluated against in turn
+    iterate: Option<JsonPathInst>,
+    /// Tags read for each match from the object holding the matched value
+    match_tags: Vec<(String, JsonPathInst)>,
+    expression: Option<Node>,
+    filter: Option<Node>,
+    bounds: Bounds,
+    /// Variables the expressions may use besides `value`, by name
+    inputs: Vec<(String, JsonPathInst)>,
+    /// Whether an expression uses `delta` or `rate`
+    counters: bool,
+    /// Variables the expressions keep between messages, with their initial values
+    persistent: Vec<(String, Value)>,
+    script: Option<Script>,
+    timestamp_path: Option<JsonPathInst>,
+    timestamp_format: TimestampFormat,
+    timezone: Tz,
+}
+
+/// The configured measurements, compiled once at startup so invalid paths and
+/// expressions are reported immediately rather than on every message.
+pub struct Pipeline {
+    measurements: Vec<PreparedMeasurement>,
+    /// Topic filters with a payload format or protobuf message type for
+    /// measurements without their own
+    topic_formats: Vec<(String, Option<PayloadFormat>, Option<usize>)>,
+    /// `mqtt_topics` entries with a script, with how the script reads their payloads
+    topic_scripts: Vec<(String, Decoding, Script)>,
+    /// `mqtt_topics` entries with their own compression, in configuration order
+    topic_compressions: Vec<(String, Compression)>,
+    compression: Compression,
+    /// A decoder for every way a payload can be read
+    decoders: Vec<(Decoding, Box<dyn Decoder>)>,
+    /// Topic filters limited to some measurements, with the indices of those measurements
+    routes: Vec<(String, Vec<usize>)>,
+    presets: Vec<Box<dyn Preset>>,
+    sink_routes: SinkRoutes,
+    global_tags: GlobalTags,
+    sanitizer: Sanitizer,
+    field_types: FieldTypes,
+}
+
+impl Pipeline {
+    pub fn new(config: &Config) -> Result<Self> {
+        let default_timezone = config.timezone.as_deref().map(parse_timezone).transpose()?.unwrap_or(Tz::UTC);
+        let descriptors = config.protobuf_descriptors.as_deref().map(protobuf::load_descriptors).transpose()?;
+        let mut protobuf_messages: Vec<MessageDescriptor> = Vec::new();
+        let mut protobuf_message = |name: &str| -> Result<usize> {
+            let pool = descriptors
+                .as_ref()
+                .ok_or_else(|| anyhow!("protobuf_message {} needs protobuf_descriptors to be set", name))?;
+            let message = protobuf::message(pool, name)?;
+            Ok(match protobuf_messages.iter().position(|m| *m == message) {
+                Some(index) => index,
+                None => {
+                    protobuf_messages.push(message);
+                    protobuf_messages.len() - 1
+                }
+            })
+        };
+        let topic_formats = config
+            .mqtt_topics
+            .iter()
+            .flatten()
+            .filter(|topic| topic.format.is_some() || topic.protobuf_message.is_some())
+            .map(|topic| {
+                let message = topic.protobuf_message.as_deref().map(&mut protobuf_message).transpose()?;
+                Ok((topic.topic.clone(), topic.format, message))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut topic_scripts = Vec::new();
+        for topic in config.mqtt_topics.iter().flatten() {
+            let Some(path) = &topic.script else {
+                continue;
+            };
+            let decoding = match topic.format.unwrap_or_default() {
+                PayloadFormat::Protobuf => match topic.protobuf_message.as_deref() {
+                    Some(name) => (PayloadFormat::Protobuf, Some(protobuf_message(name)?)),
+                    None => return Err(anyhow!("Topic {} has format protobuf but no protobuf_message", topic.topic)),
+                },
+                PayloadFormat::Binary => {
+                    return Err(anyhow!("The script of topic {} cannot read binary payloads", topic.topic));
+                }
+                format => (format, None),
+            };
+            topic_scripts.push((topic.topic.clone(), decoding, Script::load(path)?));
+        }
+        let sink_names = config.sink_names();
+        let measurements = config
+            .measurements
+            .iter()
+            .map(|m_config| {
+                if m_config.path.is_some() && m_config.column.is_some() {
+                    return Err(anyhow!("Measurement {} cannot have both a path and a column", m_config.name));
+                }
+                if m_config.format == Some(PayloadFormat::Binary) && m_config.value_type.is_none() {
+                    return Err(anyhow!("Measurement {} has format binary but no type", m_config.name));
+                }
+                // without a path the whole payload is the value, as with raw payloads
+                let column = m_config.column.as_ref().map(Column::path);
+                let path = m_config.path.as_deref().or(column.as_deref()).unwrap_or("$");
+                let path = JsonPathInst::from_str(path)
+                    .map_err(|e| anyhow!("Invalid JSONPath {} for measurement {}: {}", path, m_config.name, e))?;
+                let expression = m_config
+                    .expression
+                    .as_deref()
+                    .map(build_operator_tree)
+                    .transpose()
+                    .map_err(|e| anyhow!("Invalid expression for measurement {}: {}", m_config.name, e))?;
+                let filter = m_config
+                    .filter
+                    .as_deref()
+                    .map(build_operator_tree)
+                    .transpose()
+                    .map_err(|e| anyhow!("Invalid filter for measurement {}: {}", m_config.name, e))?;
+                let mut inputs = m_config
+                    .inputs
+                    .iter()
+                    .flatten()
+                    .map(|(input, path)| {
+                        if input == "value" {
+                            return Err(anyhow!("Measurement {} cannot have an input named value", m_config.name));
+                        }
+                        let path = JsonPathInst::from_str(path).map_err(|e| {
+                            anyhow!("Invalid JSONPath {} for input {} of measurement {}: {}", path, input, m_config.name, e)
+                        })?;
+                        Ok((input.clone(), path))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                inputs.sort_by(|a, b| a.0.cmp(&b.0));
+                let persistent = persistent::declare(m_config.variables.as_ref(), &format!("measurement {}", m_config.name))?;
+                if let Some((variable, _)) =
+                    persistent.iter().find(|(name, _)| name == "value" || inputs.iter().any(|(input, _)| input == name))
+                {
+                    return Err(anyhow!("Measurement {} has a variable and an input named {}", m_config.name, variable));
+                }
+                let timestamp_path = m_config
+                    .timestamp_path
+                    .as_deref()
+                    .map(JsonPathInst::from_str)
+                    .transpose()
+                    .map_err(|e| anyhow!("Invalid timestamp_path for measurement {}: {}", m_config.name, e))?;
+                if m_config.field.is_some() && (m_config.fields.is_some() || m_config.location.unwrap_or(false)) {
+                    return Err(anyhow!("Measurement {} cannot name its field with fields or location", m_config.name));
+                }
+                if m_config.script.is_some()
+                    && (m_config.path.is_some()
+                        || m_config.fields.is_some()
+                        || m_config.column.is_some()
+                        || m_config.value_type.is_some()
+                        || m_config.event.unwrap_or(false)
+                        || m_config.location.unwrap_or(false))
+                {
+                    return Err(anyhow!("Measurement {} cannot have both a script and a path or fields", m_config.name));
+                }
+                let bounded = m_config.min.is_some() || m_config.max.is_some() || m_config.max_step.is_some();
+                if bounded
+                    && (m_config.script.is_some()
+                        || m_config.event.unwrap_or(false)
+                        || m_config.location.unwrap_or(false))
+                {
+                    let name = &m_config.name;
+                    return Err(anyhow!("Measurement {} cannot have bounds with a script, event or location", name));
+                }
+                if m_config.fields.is_some() {
+                    if m_config.path.is_some() || m_config.column.is_some() || m_config.value_type.is_some() {
+                        return Err(anyhow!("Measurement {} cannot have both fields and a single value", m_config.name));
+                    }
+                    if m_config.location.unwrap_or(false) || m_config.event.unwrap_or(false) {
+                        return Err(anyhow!("Measurement {} with fields cannot be a location or event", m_config.name));
+                    }
+                }
+                let mut fields = m_config
+                    .fields
+                    .iter()
+                    .flatten()
+                    .map(|(field, field_config)| {
+                        let (path, expression, field_type, (min, max, max_step)) = match field_config {
+                            FieldConfig::Path(path) => (path, None, None, (None, None, None)),
+                            FieldConfig::Detailed { path, expression, field_type, min, max, max_step } => {
+                                (path, expression.as_deref(), *field_type, (*min, *max, *max_step))
+                            }
+                        };
+                        let what = format!("field {} of measurement {}", field, m_config.name);
+                        let bounds = Bounds::new(
+                            min.or(m_config.min),
+                            max.or(m_config.max),
+                            max_step.or(m_config.max_step),
+                            &what,
+                        )?;
+                        Ok(PreparedField {
+                            name: field.clone(),
+                            path: JsonPathInst::from_str(path)
+                                .map_err(|e| anyhow!("Invalid JSONPath {} for {}: {}", path, what, e))?,
+                            expression: expression
+                                .map(build_operator_tree)
+                                .transpose()
+                                .map_err(|e| anyhow!("Invalid expression for {}: {}", what, e))?,
+                            field_type,
+                            bounds,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                // sorted so the fields are written in the same order on every run
+                fields.sort_by(|a, b| a.name.cmp(&b.name));
+                let iterate = m_config
+                    .iterate
+                    .as_deref()
+                    .map(JsonPathInst::from_str)
+                    .transpose()
+                    .map_err(|e| anyhow!("Invalid iterate JSONPath for measurement {}: {}", m_config.name, e))?;
+                let match_tags = m_config
+                    .match_tags
+                    .iter()
+                    .flatten()
+                    .map(|(tag, path)| {
+                        let compiled = JsonPathInst::from_str(path).map_err(|e| {
+                            anyhow!("Invalid JSONPath {} for tag {} of measurement {}: {}", path, tag, m_config.name, e)
+                        })?;
+                        Ok((tag.clone(), compiled))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let timezone = m_config.timezone.as_deref().map(parse_timezone).transpose()?.unwrap_or(default_timezone);
+                if m_config.location.unwrap_or(false) && m_config.event.unwrap_or(false) {
+                    return Err(anyhow!("Measurement {} cannot be both a location and an event", m_config.name));
+                }
+                if m_config.map.is_some() && (m_config.location.unwrap_or(false) || m_config.event.unwrap_or(false)) {
+                    return Err(anyhow!("Measurement {} cannot map the values of a location or event", m_config.name));
+                }
+                if m_config.map_tag.is_some() && (m_config.map.is_none() || m_config.fields.is_some()) {
+                    return Err(anyhow!("Measurement {} needs a map and a single value for map_tag", m_config.name));
+                }
+                let mut map = HashMap::new();
+                for (text, mapped) in m_config.map.iter().flatten() {
+                    if mapped.is_null() || mapped.is_array() || mapped.is_object() {
+                        let what = format!("Measurement {} maps {} to {}", m_config.name, text, mapped);
+                        return Err(anyhow!("{}, expected a number, boolean or string", what));
+                    }
+                    map.insert(text.to_lowercase(), mapped.clone());
+                }
+                if m_config.geohash_precision.is_some_and(|precision| !(1..=12).contains(&precision)) {
+                    return Err(anyhow!("Invalid geohash_precision for measurement {}, expected 1 to 12", m_config.name));
+                }
+                let name = NameTemplate::parse(&m_config.name, &format!("measurement name {}", m_config.name))?;
+                // tags filled in from each message are kept apart from the fixed ones
+                let mut fixed_tags = HashMap::new();
+                let mut tag_templates = Vec::new();
+                for (tag, value) in m_config.tags.iter().flatten() {
+                    let what = format!("tag {} of measurement {}", tag, m_config.name);
+                    let template = NameTemplate::parse(value, &what)?;
+                    if template.is_fixed() {
+                        fixed_tags.insert(tag.clone(), value.clone());
+                    } else {
+                        tag_templates.push((tag.clone(), template));
+                    }
+                }
+                let message = m_config.protobuf_message.as_deref().map(&mut protobuf_message).transpose()?;
+                let what = format!("measurement {}", m_config.name);
+                let sinks = m_config.sinks.as_deref().map(|names| sink::sinks(names, &sink_names, &what)).transpose()?;
+                if m_config.format == Some(PayloadFormat::Protobuf)
+                    && message.is_none()
+                    && topic_formats.iter().all(|(_, _, message)| message.is_none())
+                {
+                    return Err(anyhow!("Measurement {} has format protobuf but no protobuf_message", m_config.name));
+                }
+                let bounds = Bounds::new(
+                    m_config.min,
+                    m_config.max,
+                    m_config.max_step,
+                    &format!("measurement {}", m_config.name),
+                )?;
+                let counters = expression
+                    .iter()
+                    .chain(fields.iter().filter_map(|field| field.expression.as_ref()))
+                    .any(|expression| expression.iter_function_identifiers().any(|name| counters::FUNCTIONS.contains(&name)));
+                Ok(PreparedMeasurement {
+                    config: MeasurementConfig {
+                        tags: (!fixed_tags.is_empty()).then_some(fixed_tags),
+                        ..m_config.clone()
+                    },
+                    tag_templates,
+                    protobuf_message: message,
+                    name: (!name.is_fixed()).then_some(name),
+                    sinks,
+                    path,
+                    fields,
+                    true_values: lowercase(m_config.true_values.as_deref()),
+                    false_values: lowercase(m_config.false_values.as_deref()),
+                    map,
+                    iterate,
+                    match_tags,
+                    counters,
+                    persistent,
+                    script: m_config.script.as_deref().map(Script::load).transpose()?,
+                    expression,
+                    filter,
+                    bounds,
+                    inputs,
+                    timestamp_path,
+                    timestamp_format: m_config.timestamp_format.or(config.timestamp_format).unwrap_or_default(),
+                    timezone,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let routes = config
+            .mqtt_topics
+            .iter()
+            .flatten()
+            .filter_map(|topic| topic.measurements.as_ref().map(|names| (topic, names)))
+            .map(|(topic, names)| {
+                let indices = names
+                    .iter()
+                    .map(|name| {
+                        measurements.iter().position(|m| &m.config.name == name).ok_or_else(|| {
+                            anyhow!("Topic {} refers to unknown measurement {}", topic.topic, name)
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((topic.topic.clone(), indices))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let csv_delimiter = match config.csv_delimiter.as_deref().map(str::as_bytes) {
+            None => b',',
+            Some([delimiter]) => *delimiter,
+            Some(_) => return Err(anyhow!("Invalid csv_delimiter, expected a single ASCII character")),
+        };
+
+        let simd_json = config.simd_json.unwrap_or(false);
+        #[cfg(not(feature = "simd-json"))]
+        if simd_json {
+            log::warn!("simd_json is enabled but this build lacks the simd-json feature, using serde_json");
+        }
+
+        let lossy_utf8 = config.lossy_utf8.unwrap_or(false);
+        let mut decoders: Vec<(Decoding, Box<dyn Decoder>)> = vec![
+            (
+                (PayloadFormat::Json, None),
+                Box::new(decoder::Json {
+                    lossy_utf8,
+                    #[cfg(feature = "simd-json")]
+                    simd_json,
+                }),
+            ),
+            ((PayloadFormat::Raw, None), Box::new(decoder::Raw { lossy_utf8 })),
+            ((PayloadFormat::Msgpack, None), Box::new(decoder::MessagePack)),
+            ((PayloadFormat::Cbor, None), Box::new(decoder::Cbor)),
+            ((PayloadFormat::Xml, None), Box::new(decoder::Xml { lossy_utf8 })),
+            (
+                (PayloadFormat::Csv, None),
+                Box::new(decoder::Csv { lossy_utf8, delimiter: csv_delimiter, header: config.csv_header.unwrap_or(false) }),
+            ),
+        ];
+        for (index, message) in protobuf_messages.into_iter().enumerate() {
+            decoders.push(((PayloadFormat::Protobuf, Some(index)), Box::new(decoder::Protobuf(message))));
+        }
+        for (index, measurement) in measurements.iter().enumerate() {
+            let m_config = &measurement.config;
+            if let Some(value_type) = m_config.value_type {
+                let field = BinaryField {
+                    offset: m_config.offset.unwrap_or(0),
+                    value_type,
+                    endianness: m_config.endianness.unwrap_or_default(),
+                    scale: m_config.scale,
+                };
+                decoders.push(((PayloadFormat::Binary, Some(index)), Box::new(decoder::Binary(field))));
+            }
+        }
+
+        Ok(Pipeline {
+            measurements,
+            topic_formats,
+            topic_scripts,
+            topic_compressions: config
+                .mqtt_topics
+                .iter()
+                .flatten()
+                .filter_map(|topic| topic.compression.map(|compression| (topic.topic.clone(), compression)))
+                .collect(),
+            compression: config.compression.unwrap_or_default(),
+            decoders,
+            routes,
+            presets: presets::build(config),
+            sink_routes: SinkRoutes::new(config.sink_routes.as_deref(), &sink_names)?,
+            global_tags: GlobalTags::new(config.global_tags.as_ref())?,
+            sanitizer: Sanitizer::new(config.sanitize.unwrap_or_default(), config.sanitize_replacement.as_deref()),
+            field_types: FieldTypes::new(config.field_type_mismatch.unwrap_or_default()),
+        })
+    }
+
+    /// How a measurement reads a message on `topic`. The format and protobuf
+    /// message type are each the measurement's own, else that of the first
+    /// matching `mqtt_topics` entry with one. The format defaults to JSON.
+    fn decoding_of(&self, index: usize, topic: &str) -> Decoding {
+        let measurement = &self.measurements[index];
+        let mut matching = self.topic_formats.iter().filter(|(filter, _, _)| topic_filter::matches(filter, topic));
+        let format = measurement
+            .config
+            .format
+            .or_else(|| matching.clone().find_map(|(_, format, _)| *format))
+            .unwrap_or_default();
+        match format {
+            PayloadFormat::Protobuf => {}
+            PayloadFormat::Binary => return (format, Some(index)),
+            _ => return (format, None),
+        }
+        (format, measurement.protobuf_message.or_else(|| matching.find_map(|(_, _, message)| *message)))
+    }
+
+    /// Decodes a payload in every format the measurements for its topic use,
+    /// failing only when it cannot be decoded in any of them.
+    pub fn decode(&self, topic: &str, payload: &[u8]) -> Result<Documents> {
+        let compression = self
+            .topic_compressions
+            .iter()
+            .find(|(filter, _)| topic_filter::matches(filter, topic))
+            .map_or(self.compression, |(_, compression)| *compression);
+        let payload = compression::decompress(compression, payload)?;
+        let payload = payload.as_ref();
+        let mut decodings = Vec::new();
+        for (index, measurement) in self.measurements.iter().enumerate() {
+            if measurement.config.topic.as_ref().is_some_and(|filter| !topic_filter::matches(filter, topic)) {
+                continue;
+            }
+            let decoding = self.decoding_of(index, topic);
+            if !decodings.contains(&decoding) {
+                decodings.push(decoding);
+            }
+        }
+        for (filter, decoding, _) in &self.topic_scripts {
+            if topic_filter::matches(filter, topic) && !decodings.contains(decoding) {
+                decodings.push(*decoding);
+            }
+        }
+        let mut first_error = None;
+        let mut documents = Vec::with_capacity(decodings.len());
+        for decoding in decodings {
+            let document = match (self.decoders.iter().find(|(d, _)| *d == decoding), decoding) {
+                (Some((_, decoder)), _) => decoder.decode(payload),
+                // a binary format set on the topic, for a measurement without a type
+                (None, (PayloadFormat::Binary, Some(index))) => {
+                    Err(anyhow!("Measurement {} has format binary but no type", self.measurements[index].config.name))
+                }
+                (None, _) => Err(anyhow!("No protobuf_message for messages on {}", topic)),
+            };
+            let document = document.map_err(|e| {
+                let message = e.to_string();
+                first_error.get_or_insert(e);
+                message
+            });
+            documents.push((decoding, document));
+        }
+        match first_error {
+            Some(e) if documents.iter().all(|(_, document)| document.is_err()) => Err(e),
+            _ => Ok(Documents { documents }),
+        }
+    }
+
+    /// Extracts every configured measurement from a message, stamping the points
+    /// with the time the message was received so queuing delays don't skew them.
+    pub fn extract(&self, message: &Message) -> Result<Extraction> {
+        // messages on a preset's topics are translated by the preset alone
+        if let Some(preset) = self.presets.iter().find(|preset| preset.handles(&message.topic)) {
+            let mut points = preset.extract(message)?;
+            for point in &mut points {
+                self.global_tags.apply(point, &message.topic);
+                self.sanitizer.apply(point);
+                point.sinks = self.sink_routes.route(&message.topic, &point.measurement);
+            }
+            return Ok(Extraction { points, errors: Vec::new() });
+        }
+        let documents = self.decode(&message.topic, &message.payload)?;
+        Ok(self.extract_from(message, &documents))
+    }
+
+    /// Extracts the configured measurements from an already decoded message. A
+    /// measurement that fails is reported without affecting the others.
+    pub fn extract_from(&self, message: &Message, documents: &Documents) -> Extraction {
+        let mut extraction = Extraction::default();
+        // a payload that is not valid in a format is reported once, not per measurement
+        let mut reported: Vec<Decoding> = Vec::new();
+        // topics listing their measurements skip evaluating all the others
+        let mut routes = self
+            .routes
+            .iter()
+            .filter(|(filter, _)| topic_filter::matches(filter, &message.topic))
+            .peekable();
+        let selected: Option<Vec<usize>> = routes
+            .peek()
+            .is_some()
+            .then(|| routes.flat_map(|(_, indices)| indices.iter().copied()).collect());
+        for (index, measurement) in self.measurements.iter().enumerate() {
+            if selected.as_ref().is_some_and(|selected| !selected.contains(&index)) {
+                continue;
+            }
+            if let Some(filter) = &measurement.config.topic
+                && !topic_filter::matches(filter, &message.topic)
+            {
+                continue;
+            }
+            let decoding = self.decoding_of(index, &message.topic);
+            let json = match documents.get(decoding) {
+                Some(Ok(json)) => json,
+                Some(Err(e)) => {
+                    if !reported.contains(&decoding) {
+                        reported.push(decoding);
+                        extraction.errors.push(anyhow!("{}", e));
+                    }
+                    continue;
+                }
+                None => continue,
+            };
+            match self.extract_measurement(measurement, message, json) {
+                Ok(points) => extraction.points.extend(points),
+                Err(e) => extraction.errors.push(e),
+            }
+        }
+        for (filter, decoding, script) in &self.topic_scripts {
+            if !topic_filter::matches(filter, &message.topic) {
+                continue;
+            }
+            let json = match documents.get(*decoding) {
+                Some(Ok(json)) => json,
+                Some(Err(e)) => {
+                    if !reported.contains(decoding) {
+                        reported.push(*decoding);
+                        extraction.errors.push(anyhow!("{}", e));
+                    }
+                    continue;
+                }
+                None => continue,
+            };
+            let defaults = ScriptDefaults { measurement: None, name: None, tags: None, timestamp: message.received_at };
+            match script.run(json, message).and_then(|produced| self.scripted_points(message, produced, &defaults)) {
+                Ok(points) => extraction.points.extend(points),
+                Err(e) => extraction.errors.push(e),
+            }
+        }
+        extraction
+    }
+
+    /// Turns what a script returned into points. Each is an object with the
+    /// `measurement`, unless a measurement's script defaults it to that
+    /// measurement's name, its `fields` or a single `value`, and optional
+    /// `tags` and `timestamp`. A measurement's script may also return bare
+    /// values, written as its field.
+    fn scripted_points(
+        &self,
+        message: &Message,
+        produced: Vec<serde_json::Value>,
+        defaults: &ScriptDefaults,
+    ) -> Result<Vec<Point>> {
+        let mut points = Vec::with_capacity(produced.len());
+        for item in produced {
+            let item = match item {
+                serde_json::Value::Object(item) => item,
+                value if defaults.measurement.is_some() => {
+                    serde_json::Map::from_iter([("value".to_string(), value)])
+                }
+                value => return Err(anyhow!("Script for {} returned {} instead of an object", message.topic, value)),
+            };
+            let name = match (item.get("measurement"), defaults.name) {
+                (Some(serde_json::Value::String(name)), _) => name.clone(),
+                (None, Some(name)) => name.to_string(),
+                _ => return Err(anyhow!("Script for {} returned a point without a measurement name", message.topic)),
+            };
+            let mut tags = defaults.tags.clone();
+            if let Some(serde_json::Value::Object(extra)) = item.get("tags") {
+                for (tag, value) in extra {
+                    if let Some(value) = tag_value(value) {
+                        tags.get_or_insert_with(HashMap::new).insert(tag.clone(), value);
+                    }
+                }
+            }
+            let measurement = defaults.measurement;
+            let single = measurement.and_then(|measurement| measurement.config.field.as_deref()).unwrap_or("value");
+            let values: Vec<(&str, &serde_json::Value)> = match (item.get("fields"), item.get("value")) {
+                (Some(serde_json::Value::Object(fields)), _) => {
+                    fields.iter().map(|(field, value)| (field.as_str(), value)).collect()
+                }
+                (None, Some(value)) => vec![(single, value)],
+                _ => {
+                    return Err(anyhow!("Script for {} returned point {} without fields or a value", message.topic, name));
+                }
+            };
+            let mut fields = Vec::with_capacity(values.len());
+            for (field, value) in values {
+                let checked = format!("{} field {}", name, field);
+                let value = match (value, measurement) {
+                    (serde_json::Value::String(text), _) => Some(FieldValue::String(text.clone())),
+                    (value, Some(measurement)) => {
+                        let field_type = measurement.config.field_type.unwrap_or_default();
+                        self.field_value(measurement, &checked, field_type, None, value)?
+                    }
+                    (serde_json::Value::Bool(flag), None) => Some(FieldValue::Boolean(*flag)),
+                    (value, None) => value.as_f64().map(FieldValue::Float),
+                };
+                if let Some(value) = value {
+                    fields.push((field.to_string(), value));
+                }
+            }
+            if fields.is_empty() {
+                continue;
+            }
+            let timestamp = match item.get("timestamp") {
+                Some(timestamp) => {
+                    let (format, timezone) = measurement
+                        .map_or((TimestampFormat::default(), Tz::UTC), |m| (m.timestamp_format, m.timezone));
+                    parse_timestamp(timestamp, format, timezone)
+                        .map_err(|e| anyhow!("Invalid timestamp from the script for {}: {}", message.topic, e))?
+                }
+                None => defaults.timestamp,
+            };
+            let sinks = measurement
+                .and_then(|measurement| measurement.sinks.clone())
+                .or_else(|| self.sink_routes.route(&message.topic, &name));
+            let mut point = Point {
+                measurement: name,
+                fields,
+                tags,
+                bucket: measurement.and_then(|measurement| measurement.config.bucket.clone()),
+                sinks,
+                timestamp,
+            };
+            self.global_tags.apply(&mut point, &message.topic);
+            self.sanitizer.apply(&mut point);
+            points.push(point);
+        }
+        Ok(points)
+    }
+
+    /// Extracts a point for every value the measurement's path matches, in
+    /// each element `iterate` matches when it is set.
+    fn extract_measurement(
+        &self,
+        measurement: &PreparedMeasurement,
+        message: &Message,
+        json: &serde_json::Value,
+    ) -> Result<Vec<Point>> {
+        let Some(iterate) = &measurement.iterate else {
+            return self.extract_matches(measurement, message, json, None);
+        };
+        let mut points = Vec::new();
+        for (position, element) in iterate.find_slice(json).iter().enumerate() {
+            points.extend(self.extract_matches(measurement, message, element, Some(position))?);
+        }
+        Ok(points)
+    }
+
+    /// Extracts a point for every value the path matches in `json`, either the
+    /// payload or the element of the payload at `position` of those `iterate`
+    /// matches.
+    fn extract_matches(
+        &self,
+        measurement: &PreparedMeasurement,
+        message: &Message,
+        json: &serde_json::Value,
+        position: Option<usize>,
+    ) -> Result<Vec<Point>> {
+        let m_config = &measurement.config;
+        // a measurement with fields writes one point with all of them
+        let found = if measurement.fields.is_empty() {
+            measurement.path.find_slice(json)
+        } else {
+            vec![JsonPtr::Slice(json)]
+        };
+        if found.is_empty() {
+            return Ok(Vec::new());
+        }
+        let Some(variables) = measurement.variables(message, json) else {
+            return Ok(Vec::new());
+        };
+        let name = match &measurement.name {
+            Some(template) => match template.render(&message.topic, json) {
+                Some(name) => name,
+                None => return Ok(Vec::new()),
+            },
+            None => m_config.name.clone(),
+        };
+
+        let mut tags = m_config.tags.clone();
+        for (tag, template) in &measurement.tag_templates {
+            if let Some(value) = template.render(&message.topic, json) {
+                tags.get_or_insert_with(HashMap::new).insert(tag.clone(), value);
+            }
+        }
+        if let Some(filter) = &m_config.topic {
+            for (name, level) in topic_filter::captures(filter, &message.topic) {
+                tags.get_or_insert_with(HashMap::new).insert(name.to_string(), level.to_string());
+            }
+        }
+
+        // points without a device timestamp fall back to the receipt time
+        let timestamp = match measurement
+            .timestamp_path
+            .as_ref()
+            .and_then(|path| path.find_slice(json).into_iter().next())
+        {
+            Some(device_timestamp) => parse_timestamp(&device_timestamp, measurement.timestamp_format, measurement.timezone)
+                .map_err(|e| anyhow!("Invalid timestamp for measurement {}: {}", m_config.name, e))?,
+            None => message.received_at,
+        };
+
+        if let Some(script) = &measurement.script {
+            let defaults = ScriptDefaults { measurement: Some(measurement), name: Some(&name), tags, timestamp };
+            return self.scripted_points(message, script.run(json, message)?, &defaults);
+        }
+        let sinks = measurement.sinks.clone().or_else(|| self.sink_routes.route(&message.topic, &name));
+        let mut points = Vec::with_capacity(found.len());
+        for (index, val) in found.iter().enumerate() {
+            // the points of iterated elements are told apart by the element's position
+            let index = position.unwrap_or(index);
+            let mut tags = tags.clone();
+            if let Some(tag) = &m_config.index_tag {
+                tags.get_or_insert_with(HashMap::new).insert(tag.clone(), index.to_string());
+            }
+            if !measurement.match_tags.is_empty()
+                && let JsonPtr::Slice(val) = val
+                && let Some(parent) = parent_of(json, val)
+            {
+                for (tag, path) in &measurement.match_tags {
+                    if let Some(value) = path.find_slice(parent).first().and_then(|value| tag_value(value)) {
+                        tags.get_or_insert_with(HashMap::new).insert(tag.clone(), value);
+                    }
+                }
+            }
+            let stateful = measurement.counters
+                || !measurement.persistent.is_empty()
+                || measurement.bounds.max_step.is_some()
+                || measurement.fields.iter().any(|field| field.bounds.max_step.is_some());
+            let series = stateful.then(|| series_name(&name, tags.as_ref()));
+            let scope = Scope {
+                variables: &variables,
+                series: series.as_deref(),
+                field: "",
+                timestamp,
+                counters: measurement.counters,
+                persistent: &measurement.persistent,
+            };
+            let Some(fields) = self.fields_of(measurement, message, index, val, scope, &mut tags)? else {
+                continue;
+            };
+            if let Some(filter) = &measurement.filter
+                && !passes(filter, &m_config.name, &fields, &variables)?
+            {
+                METRICS.filtered_points.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let mut point = Point {
+                measurement: name.clone(),
+                fields,
+                tags,
+                bucket: m_config.bucket.clone(),
+                sinks: sinks.clone(),
+                timestamp,
+            };
+            self.global_tags.apply(&mut point, &message.topic);
+            self.sanitizer.apply(&mut point);
+            points.push(point);
+        }
+        Ok(points)
+    }
+
+    /// The fields of the point for one matched value, or None when the value
+    /// doesn't yield one.
+    fn fields_of(
+        &self,
+        measurement: &PreparedMeasurement,
+        message: &Message,
+        index: usize,
+        val: &serde_json::Value,
+        scope: Scope,
+        tags: &mut Option<HashMap<String, String>>,
+    ) -> Result<Option<Vec<(String, FieldValue)>>> {
+        let m_config = &measurement.config;
+        if !measurement.fields.is_empty() {
+            let mut fields = Vec::with_capacity(measurement.fields.len());
+            for field in &measurement.fields {
+                let Some(found) = field.path.find_slice(val).into_iter().next() else {
+                    continue;
+                };
+                let found = measurement.mapped(&found)?.unwrap_or(&found);
+                let checked = format!("{} field {}", m_config.name, field.name);
+                let field_type = field.field_type.or(m_config.field_type).unwrap_or_default();
+                let scope = Scope { field: &field.name, ..scope };
+                let expression = field.expression.as_ref().map(|expression| (expression, &scope));
+                if let Some(value) = self.field_value(measurement, &checked, field_type, expression, found)?
+                    && plausible(&field.bounds, &scope, &value)
+                {
+                    fields.push((field.name.clone(), value));
+                }
+            }
+            return Ok((!fields.is_empty()).then_some(fields));
+        }
+        if m_config.location.unwrap_or(false) {
+            let Some((lat, lon)) = geo::parse_location(val) else {
+                return Ok(None);
+            };
+            if let Some(precision) = m_config.geohash_precision {
+                tags.get_or_insert_with(HashMap::new)
+                    .insert("geohash".to_string(), geo::geohash(lat, lon, precision));
+            }
+            Ok(Some(vec![
+                ("lat".to_string(), FieldValue::Float(lat)),
+                ("lon".to_string(), FieldValue::Float(lon)),
+            ]))
+        } else if m_config.event.unwrap_or(false) {
+            let Some(text) = tag_value(val) else {
+                return Ok(None);
+            };
+            // an event repeated in every status message is only written when it changes
+            let section = format!("events/{}", m_config.name);
+            let key = match index {
+                0 => message.topic.clone(),
+                _ => format!("{}[{}]", message.topic, index),
+            };
+            let changed = STATE.update(&section, &key, |last| {
+                let changed = last.as_ref().and_then(|last| last.as_str()) != Some(text.as_str());
+                *last = Some(text.clone().into());
+                changed
+            });
+            if !changed {
+                return Ok(None);
+            }
+            let field = m_config.field.as_deref().unwrap_or("text");
+            Ok(Some(vec![(field.to_string(), FieldValue::String(text))]))
+        } else {
+            let field = m_config.field.as_deref().unwrap_or("value");
+            let field_type = m_config.field_type.unwrap_or_default();
+            let scope = Scope { field, ..scope };
+            let mapped = measurement.mapped(val)?;
+            if let (Some(tag), Some(_), Some(text)) = (&m_config.map_tag, mapped, val.as_str()) {
+                tags.get_or_insert_with(HashMap::new).insert(tag.clone(), text.to_string());
+            }
+            let val = mapped.unwrap_or(val);
+            let expression = measurement.expression.as_ref().map(|expression| (expression, &scope));
+            Ok(self
+                .field_value(measurement, &m_config.name, field_type, expression, val)?
+                .filter(|value| plausible(&measurement.bounds, &scope, value))
+                .map(|value| vec![(field.to_string(), value)]))
+        }
+    }
+
+    /// Converts a value to a field of the given type, applying the expression
+    /// with its variables. Integers in the payload are written exactly when
+    /// there is no expression, other values are rounded.
+    fn field_value(
+        &self,
+        measurement: &PreparedMeasurement,
+        checked: &str,
+        field_type: FieldType,
+        expression: Option<(&Node, &Scope)>,
+        val: &serde_json::Value,
+    ) -> Result<Option<FieldValue>> {
+        let text = val.as_str().map(str::trim);
+        // booleans and the strings mapped to them are written as such, or as 1 and 0
+        let flag = match val {
+            serde_json::Value::Bool(flag) => Some(*flag),
+            _ => text.and_then(|text| measurement.boolean(text)),
+        };
+        let number;
+        let val = match flag {
+            Some(flag) if field_type == FieldType::Bool && expression.is_none() => {
+                return Ok(Some(FieldValue::Boolean(flag)));
+            }
+            Some(flag) => {
+                number = serde_json::Value::from(u8::from(flag));
+                &number
+            }
+            None => val,
+        };
+        if expression.is_none() {
+            let exact = match field_type {
+                FieldType::Float | FieldType::Bool => None,
+                FieldType::Int => val.as_i64().or_else(|| text?.parse().ok()).map(FieldValue::Integer),
+                FieldType::Uint => val.as_u64().or_else(|| text?.parse().ok()).map(FieldValue::UInteger),
+            };
+            if let Some(exact) = exact {
+                self.field_types.check(checked, val)?;
+                return Ok(Some(exact));
+            }
+        }
+        let Some(value) = self.numeric_value(checked, expression, val)? else {
+            return Ok(None);
+        };
+        Ok(Some(match field_type {
+            // InfluxDB rejects inf and NaN, failing the whole batch they are written in
+            FieldType::Float if value.is_finite() => FieldValue::Float(value),
+            FieldType::Bool => FieldValue::Boolean(value != 0.0),
+            FieldType::Int if value.is_finite() => FieldValue::Integer(value.round() as i64),
+            FieldType::Uint if value.is_finite() && value > -0.5 => FieldValue::UInteger(value.round() as u64),
+            _ => return Err(anyhow!("Measurement {} value {} is not a valid {:?} field", checked, value, field_type)),
+        }))
+    }
+
+    /// Converts a value to a number and applies its expression. `checked` names
+    /// the value when checking its type.
+    fn numeric_value(
+        &self,
+        checked: &str,
+        expression: Option<(&Node, &Scope)>,
+        val: &serde_json::Value,
+    ) -> Result<Option<f64>> {
+        self.field_types.check(checked, val)?;
+        let mut float_val = if val.is_number() {
+            val.as_f64().unwrap_or(0.0)
+        } else if val.is_string() {
+            val.as_str().unwrap_or("0").parse::<f64>().unwrap_or(0.0)
+        } else {
+            return Ok(None);
+        };
+
+        if let Some((expr, scope)) = expression {
+            let mut context = HashMapContext::new();
+            for (name, variable) in scope.variables {
+                context.set_value(name.clone(), variable.clone())?;
+            }
+            context.set_value("value".into(), Value::Float(float_val))?;
+            units::install(expr, &mut context)?;
+            let key = scope.series.map(|series| format!("{} {}", series, scope.field));
+            let counter = key.clone().filter(|_| scope.counters).map(|key| Counter::new(key, scope.timestamp));
+            if let Some(counter) = &counter {
+                counter.install(&mut context)?;
+            }
+            let persistent = key.filter(|_| !scope.persistent.is_empty()).map(|key| Persistent::load(key, scope.persistent));
+            if let Some(persistent) = &persistent {
+                persistent.install(&mut context)?;
+            }
+            // a failed expression leaves the counters and variables as they were
+            let eval_res = expr
+                .eval_with_context_mut(&mut context)
+                .map_err(|e| anyhow!("Failed to evaluate the expression of measurement {}: {}", checked, e))?;
+            if let Some(counter) = counter {
+                counter.save();
+            }
+            if let Some(persistent) = persistent {
+                persistent.save(&context);
+            }
+            // as for the first reading of a counter, when there is nothing to write
+            if eval_res.is_empty() {
+                return Ok(None);
+            }
+            if let Ok(f) = eval_res.as_float() {
+                float_val = f;
+            } else if let Ok(i) = eval_res.as_int() {
+                float_val = i as f64;
+            }
+        }
+        Ok(Some(float_val))
+    }
+}
+
+/// Evaluates the filter of a measurement on the fields of a point, each a
+/// variable of its name, with the only one also being `value`.
+fn passes(filter: &Node, measurement: &str, fields: &[(String, FieldValue)], variables: &[(String, Value)]) -> Result<bool> {
+    let mut context = HashMapContext::new();
+    for (name, variable) in variables {
+        context.set_value(name.clone(), variable.clone())?;
+    }
+    for (name, field) in fields {
+        let variable = match field {
+            FieldValue::Float(value) => Value::Float(*value),
+            FieldValue::Integer(value) => Value::Int(*value),
+            FieldValue::UInteger(value) => i64::try_from(*value).map_or(Value::Float(*value as f64), Value::Int),
+            FieldValue::Boolean(flag) => Value::Boolean(*flag),
+            FieldValue::String(text) => Value::String(text.clone()),
+        };
+        if fields.len() == 1 && name != "value" {
+            context.set_value("value".into(), variable.clone())?;
+        }
+        context.set_value(name.clone(), variable)?;
+    }
+    units::install(filter, &mut context)?;
+    filter
+        .eval_boolean_with_context(&context)
+        .map_err(|e| anyhow!("Failed to evaluate the filter of measurement {}: {}", measurement, e))
+}
+
+/// Whether a numeric value is within its bounds, counting those that are not.
+fn plausible(bounds: &Bounds, scope: &Scope, value: &FieldValue) -> bool {
+    let reading = match value {
+        _ if !bounds.is_set() => return true,
+        FieldValue::Float(value) => *value,
+        FieldValue::Integer(value) => *value as f64,
+        FieldValue::UInteger(value) => *value as f64,
+        FieldValue::Boolean(_) | FieldValue::String(_) => return true,
+    };
+    let key = scope.series.map(|series| format!("{} {}", series, scope.field));
+    if bounds.accepts(key.as_deref(), reading) {
+        return true;
+    }
+    METRICS.rejected_readings.fetch_add(1, Ordering::Relaxed);
+    false
+}
+
+/// The measurement and sorted tags of a point, which identify its series.
+fn series_name(measurement: &str, tags: Option<&HashMap<String, String>>) -> String {
+    let mut tags: Vec<_> = tags.into_iter().flatten().collect();
+    tags.sort();
+    let mut name = measurement.to_string();
+    for (tag, value) in tags {
+        name.push_str(&format!(",{}={}", tag, value));
+    }
+    name
+}
+
+fn lowercase(values: Option<&[String]>) -> Vec<String> {
+    values.into_iter().flatten().map(|value| value.to_lowercase()).collect()
+}
+
+impl PreparedMeasurement {
+    /// What the `map` replaces a string with, ignoring case and surrounding
+    /// spaces. Strings it lacks are errors rather than being written as 0, the
+    /// code of some other string, unless they are `true_values` or `false_values`.
+    fn mapped(&self, val: &serde_json::Value) -> Result<Option<&serde_json::Value>> {
+        let Some(text) = val.as_str().filter(|_| !self.map.is_empty()) else {
+            return Ok(None);
+        };
+        match self.map.get(&text.trim().to_lowercase()) {
+            Some(mapped) => Ok(Some(mapped)),
+            None if self.boolean(text.trim()).is_some() => Ok(None),
+            None => Err(anyhow!("Measurement {} has no mapping for {}", self.config.name, val)),
+        }
+    }
+
+    /// The variables of the expressions for a message: those describing the
+    /// message, then the values of the inputs in a document, or None when an
+    /// input is missing. Numbers from the payload are always floats, so
+    /// `power / voltage` never divides integers.
+    fn variables(&self, message: &Message, json: &serde_json::Value) -> Option<Vec<(String, Value)>> {
+        // the message variables are only worth building when an expression can read them
+        let expressions = self.expression.is_some() || self.filter.is_some();
+        let mut variables = if expressions || self.fields.iter().any(|field| field.expression.is_some()) {
+            message.variables()
+        } else {
+            Vec::new()
+        };
+        for (name, path) in &self.inputs {
+            let found = path.find_slice(json).into_iter().next()?;
+            let variable = match &*found {
+                serde_json::Value::Number(number) => Value::Float(number.as_f64()?),
+                serde_json::Value::String(text) => Value::String(text.clone()),
+                serde_json::Value::Bool(flag) => Value::Boolean(*flag),
+                _ => return None,
+            };
+            variables.push((name.clone(), variable));
+        }
+        Some(variables)
+    }
+
+    /// The boolean a string such as `ON` or `closed` maps to, ignoring case.
+    fn boolean(&self, text: &str) -> Option<bool> {
+        let text = text.to_lowercase();
+        if self.true_values.contains(&text) {
+            Some(true)
+        } else if self.false_values.contains(&text) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// Finds the object or array directly holding `child`. JSONPath matches borrow
+/// from the document, so the child is recognised by its address.
+fn parent_of<'a>(node: &'a serde_json::Value, child: &serde_json::Value) -> Option<&'a serde_json::Value> {
+    let children: Vec<&serde_json::Value> = match node {
+        serde_json::Value::Object(members) => members.values().collect(),
+        serde_json::Value::Array(items) => items.iter().collect(),
+        _ => return None,
+    };
+    if children.iter().any(|candidate| std::ptr::eq(*candidate, child)) {
+        return Some(node);
+    }
+    children.into_iter().find_map(|candidate| parent_of(candidate, child))
+}
+
+/// A scalar value as text, for tags and event fields.
+fn tag_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(text) => Some(text.clone()),
+        serde_json::Value::Number(number) => Some(number.to_string()),
+        serde_json::Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+/// The points extracted from one message, along with the errors of any
+/// measurements that could not be extracted.
+#[derive(Default)]
+pub struct Extraction {
+    pub points: Vec<Point>,
+    pub errors: Vec<anyhow::Error>,
+}
+
+/// Reports the errors of all failed measurements of a message together.
+pub fn combine_errors(mut errors: Vec<anyhow::Error>) -> Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        count => Err(anyhow!(
+            "{} measurements failed: {}",
+            count,
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ")
+        )),
+    }
+}
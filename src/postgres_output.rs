@@ -0,0 +1,235 @@
+#[cfg(not(feature = "postgres"))]
+use anyhow::Result;
+#[cfg(not(feature = "postgres"))]
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[cfg(not(feature = "postgres"))]
+use crate::pipeline::Point;
+#[cfg(not(feature = "postgres"))]
+use crate::sink::Sink;
+
+#[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub struct PostgresOutputConfig {
+    /// Connection string, e.g. `host=localhost user=bridge dbname=telemetry`
+    /// or `postgres://bridge@localhost/telemetry`
+    pub url: String,
+    /// Table the points are inserted into, optionally with its schema
+    pub table: String,
+    pub columns: Option<PostgresColumns>,
+    /// Tags stored in columns of their own, by tag name
+    pub tag_columns: Option<HashMap<String, String>>,
+}
+
+/// Names of the columns a row is made of, one row per field. An empty name
+/// leaves the column out, apart from `time` and `value`.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub struct PostgresColumns {
+    pub time: Option<String>,
+    pub measurement: Option<String>,
+    /// A `jsonb` column holding every tag
+    pub tags: Option<String>,
+    pub field: Option<String>,
+    pub value: Option<String>,
+}
+
+#[cfg(not(feature = "postgres"))]
+pub struct PostgresOutput;
+
+#[cfg(not(feature = "postgres"))]
+impl PostgresOutput {
+    pub fn new(_config: &PostgresOutputConfig) -> Result<Self> {
+        Err(anyhow::anyhow!("postgres_output is configured but this build lacks the postgres feature"))
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+#[async_trait]
+impl Sink for PostgresOutput {
+    async fn write_batch(&self, _points: &[Point], _bucket: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use enabled::PostgresOutput;
+
+#[cfg(feature = "postgres")]
+mod enabled {
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use log::{debug, error};
+    use tokio::sync::Mutex;
+    use tokio_postgres::types::ToSql;
+    use tokio_postgres::{Client, NoTls};
+
+    use super::PostgresOutputConfig;
+    use crate::pipeline::{FieldValue, Point};
+    use crate::sink::Sink;
+
+    /// The most parameters PostgreSQL accepts in one statement.
+    const MAX_PARAMETERS: usize = 65535;
+
+    /// A value of a row, in the type of its column.
+    enum Column {
+        Time,
+        Measurement,
+        Tags,
+        Field,
+        Value,
+        Tag(String),
+    }
+
+    /// Inserts points into a PostgreSQL or TimescaleDB table, one row per
+    /// field and one statement per batch. Fields holding text are left out.
+    pub struct PostgresOutput {
+        url: String,
+        client: Mutex<Option<Client>>,
+        /// The beginning of the insert statement, up to `VALUES`
+        insert: String,
+        columns: Vec<Column>,
+    }
+
+    impl PostgresOutput {
+        pub fn new(config: &PostgresOutputConfig) -> Result<Self> {
+            let names = config.columns.clone().unwrap_or_default();
+            let named = |name: Option<String>, default: &str| name.unwrap_or_else(|| default.to_string());
+            let mut columns = vec![
+                (named(names.time, "time"), Column::Time),
+                (named(names.measurement, "measurement"), Column::Measurement),
+                (named(names.tags, "tags"), Column::Tags),
+                (named(names.field, "field"), Column::Field),
+                (named(names.value, "value"), Column::Value),
+            ];
+            let mut tag_columns: Vec<_> = config.tag_columns.iter().flatten().collect();
+            tag_columns.sort();
+            for (tag, column) in tag_columns {
+                columns.push((column.clone(), Column::Tag(tag.clone())));
+            }
+            columns.retain(|(name, column)| !name.is_empty() || matches!(column, Column::Time | Column::Value));
+            if let Some((_, column)) = columns.iter().find(|(name, _)| name.is_empty()) {
+                let which = if matches!(column, Column::Time) { "time" } else { "value" };
+                return Err(anyhow!("The {} column of postgres_output cannot be left out", which));
+            }
+            let names: Vec<_> = columns.iter().map(|(name, _)| quote(name)).collect();
+            Ok(PostgresOutput {
+                url: config.url.clone(),
+                client: Mutex::new(None),
+                insert: format!("INSERT INTO {} ({}) VALUES ", quote(&config.table), names.join(", ")),
+                columns: columns.into_iter().map(|(_, column)| column).collect(),
+            })
+        }
+
+        async fn insert_rows(&self, points: &[Point]) -> Result<()> {
+            let mut rows: Vec<Vec<Box<dyn ToSql + Sync + Send>>> = Vec::new();
+            for point in points {
+                for (field, value) in &point.fields {
+                    let value = match value {
+                        FieldValue::Float(value) => *value,
+                        FieldValue::Integer(value) => *value as f64,
+                        FieldValue::UInteger(value) => *value as f64,
+                        FieldValue::Boolean(flag) => f64::from(u8::from(*flag)),
+                        FieldValue::String(_) => {
+                            debug!("Not inserting text field {} of {} into PostgreSQL", field, point.measurement);
+                            continue;
+                        }
+                    };
+                    rows.push(self.columns.iter().map(|column| row_value(column, point, field, value)).collect());
+                }
+            }
+            if rows.is_empty() {
+                return Ok(());
+            }
+
+            let mut client = self.client.lock().await;
+            let client = match &mut *client {
+                Some(client) if !client.is_closed() => client,
+                // connected on the first write and again after the connection is lost
+                slot => slot.insert(self.connect().await?),
+            };
+            for rows in rows.chunks(MAX_PARAMETERS / self.columns.len()) {
+                let mut statement = self.insert.clone();
+                let mut parameters: Vec<&(dyn ToSql + Sync)> = Vec::new();
+                for (index, row) in rows.iter().enumerate() {
+                    statement.push_str(if index == 0 { "(" } else { ", (" });
+                    for (column, value) in row.iter().enumerate() {
+                        parameters.push(value.as_ref());
+                        statement.push_str(&format!("{}${}", if column == 0 { "" } else { ", " }, parameters.len()));
+                    }
+                    statement.push(')');
+                }
+                client.execute(&statement, &parameters).await.map_err(|e| {
+                    let message = explain(&e);
+                    // kept as the source for `is_permanent`
+                    anyhow::Error::new(e).context(format!("Failed to insert into PostgreSQL: {}", message))
+                })?;
+            }
+            Ok(())
+        }
+
+        async fn connect(&self) -> Result<Client> {
+            let (client, connection) = tokio_postgres::connect(&self.url, NoTls)
+                .await
+                .map_err(|e| anyhow!("Failed to connect to PostgreSQL: {}", explain(&e)))?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    error!("PostgreSQL connection failed: {}", e);
+                }
+            });
+            Ok(client)
+        }
+    }
+
+    #[async_trait]
+    impl Sink for PostgresOutput {
+        async fn write_batch(&self, points: &[Point], _bucket: &str) -> Result<()> {
+            self.insert_rows(points).await
+        }
+
+        fn is_retryable(&self, error: &anyhow::Error) -> bool {
+            !is_permanent(error)
+        }
+    }
+
+    fn row_value(column: &Column, point: &Point, field: &str, value: f64) -> Box<dyn ToSql + Sync + Send> {
+        match column {
+            Column::Time => Box::new(point.timestamp),
+            Column::Measurement => Box::new(point.measurement.clone()),
+            Column::Tags => Box::new(serde_json::to_value(point.tags.clone().unwrap_or_default()).unwrap_or_default()),
+            Column::Field => Box::new(field.to_string()),
+            Column::Value => Box::new(value),
+            Column::Tag(tag) => Box::new(point.tags.as_ref().and_then(|tags| tags.get(tag)).cloned()),
+        }
+    }
+
+    /// The server's message or the underlying cause, which the error itself
+    /// leaves out.
+    fn explain(error: &tokio_postgres::Error) -> String {
+        if let Some(db_error) = error.as_db_error() {
+            return db_error.message().to_string();
+        }
+        match std::error::Error::source(error) {
+            Some(source) => format!("{}: {}", error, source),
+            None => error.to_string(),
+        }
+    }
+
+    /// Quotes a table or column name, keeping a schema such as `public.readings`.
+    fn quote(name: &str) -> String {
+        name.split('.').map(|part| format!("\"{}\"", part.replace('"', "\"\""))).collect::<Vec<_>>().join(".")
+    }
+
+    /// Whether PostgreSQL refused the rows themselves, for example because a
+    /// column does not exist or a value does not fit it, so inserting them
+    /// again would fail the same way.
+    fn is_permanent(error: &anyhow::Error) -> bool {
+        let Some(code) = error.downcast_ref::<tokio_postgres::Error>().and_then(|e| e.code()) else {
+            return false;
+        };
+        // data exceptions, integrity constraint violations and syntax or access rule violations
+        matches!(&code.code()[..2], "22" | "23" | "42")
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use log::debug;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{Preset, levels, point};
+use crate::pipeline::{Message, Point};
+use crate::state::STATE;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OpenEvseConfig {
+    /// The MQTT base topic configured in OpenEVSE, defaults to `openevse`
+    pub topic_prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GoEConfig {
+    /// Defaults to `go-eCharger`, the charger's serial number follows it
+    pub topic_prefix: Option<String>,
+}
+
+/// Lifetime energy only ever grows, but chargers briefly report zero or a
+/// stale value after rebooting. Dropping readings that go backwards keeps
+/// `increase()` and `non_negative_difference()` queries correct.
+fn accept_lifetime_energy(charger: &str, energy: f64) -> bool {
+    STATE.update("evse_total_energy", charger, |last| {
+        if let Some(previous) = last.as_ref().and_then(|last| last.as_f64())
+            && energy < previous
+        {
+            debug!("Ignoring lifetime energy of {} going back from {} to {}", charger, previous, energy);
+            return false;
+        }
+        *last = Some(energy.into());
+        true
+    })
+}
+
+/// OpenEVSE: a bare value per `openevse/<name>` topic.
+pub struct OpenEvse {
+    prefix: String,
+    topics: Vec<String>,
+}
+
+impl OpenEvse {
+    pub fn new(config: &OpenEvseConfig) -> Self {
+        let prefix = config.topic_prefix.clone().unwrap_or_else(|| "openevse".to_string());
+        OpenEvse {
+            topics: vec![format!("{}/+", prefix)],
+            prefix,
+        }
+    }
+}
+
+impl Preset for OpenEvse {
+    fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    fn extract(&self, message: &Message) -> Result<Vec<Point>> {
+        let Some(levels) = levels(&message.topic, &self.prefix) else {
+            return Ok(Vec::new());
+        };
+        let Some(Ok(value)) = std::str::from_utf8(&message.payload).ok().map(|payload| payload.trim().parse::<f64>())
+        else {
+            return Ok(Vec::new());
+        };
+        let (measurement, value) = match levels.as_slice() {
+            ["amp"] => ("evse_current", value / 1000.0),
+            ["pilot"] => ("evse_pilot", value),
+            ["state"] => ("evse_state", value),
+            ["temp"] => ("evse_temperature", value / 10.0),
+            ["voltage"] => ("evse_voltage", value),
+            ["power"] => ("evse_power", value),
+            ["session_energy"] => ("evse_session_energy", value),
+            ["total_energy"] => {
+                let energy = value * 1000.0;
+                if !accept_lifetime_energy(&self.prefix, energy) {
+                    return Ok(Vec::new());
+                }
+                ("evse_total_energy", energy)
+            }
+            _ => return Ok(Vec::new()),
+        };
+        let tags = [("charger", self.prefix.as_str())];
+        Ok(vec![point(measurement, value, &tags, message.received_at)])
+    }
+}
+
+/// go-eCharger API v2: a JSON value per `go-eCharger/<serial>/<key>` topic.
+pub struct GoE {
+    prefix: String,
+    topics: Vec<String>,
+}
+
+impl GoE {
+    pub fn new(config: &GoEConfig) -> Self {
+        let prefix = config.topic_prefix.clone().unwrap_or_else(|| "go-eCharger".to_string());
+        GoE {
+            topics: vec![format!("{}/+/+", prefix)],
+            prefix,
+        }
+    }
+}
+
+impl Preset for GoE {
+    fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    fn extract(&self, message: &Message) -> Result<Vec<Point>> {
+        let Some(levels) = levels(&message.topic, &self.prefix) else {
+            return Ok(Vec::new());
+        };
+        let [serial, key] = levels.as_slice() else {
+            return Ok(Vec::new());
+        };
+        let Ok(value) = serde_json::from_slice::<Value>(&message.payload) else {
+            return Ok(Vec::new());
+        };
+        let tags = [("charger", *serial)];
+        let at = message.received_at;
+        let mut points = Vec::new();
+        match *key {
+            "amp" => points.extend(value.as_f64().map(|amps| point("evse_pilot", amps, &tags, at))),
+            "car" => points.extend(value.as_f64().map(|state| point("evse_state", state, &tags, at))),
+            "wh" => points.extend(value.as_f64().map(|energy| point("evse_session_energy", energy, &tags, at))),
+            "eto" => {
+                if let Some(energy) = value.as_f64()
+                    && accept_lifetime_energy(serial, energy)
+                {
+                    points.push(point("evse_total_energy", energy, &tags, at));
+                }
+            }
+            "tma" => {
+                // the first sensor is the charger's main board
+                if let Some(temperature) = value[0].as_f64() {
+                    points.push(point("evse_temperature", temperature, &tags, at));
+                }
+            }
+            "nrg" => {
+                // voltages L1-L3 and N, currents L1-L3, powers L1-L3 and N, total power
+                for (phase, index) in [("1", 4), ("2", 5), ("3", 6)] {
+                    if let Some(amps) = value[index].as_f64() {
+                        points.push(point("evse_current", amps, &[("charger", serial), ("phase", phase)], at));
+                    }
+                }
+                if let Some(voltage) = value[0].as_f64() {
+                    points.push(point("evse_voltage", voltage, &tags, at));
+                }
+                if let Some(power) = value[11].as_f64() {
+                    points.push(point("evse_power", power, &tags, at));
+                }
+            }
+            _ => {}
+        }
+        Ok(points)
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{Preset, levels, point};
+use crate::pipeline::{Message, Point};
+
+const CAMERA_STATS: &[&str] = &["camera_fps", "process_fps", "skipped_fps", "detection_fps"];
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct FrigateConfig {
+    /// The `mqtt.topic_prefix` configured in Frigate, defaults to `frigate`
+    pub topic_prefix: Option<String>,
+}
+
+/// Frigate NVR: object counts from `frigate/<camera>/<object>`, detections
+/// and their scores from `frigate/events` and camera and detector performance
+/// from `frigate/stats`.
+pub struct Frigate {
+    prefix: String,
+    topics: Vec<String>,
+}
+
+impl Frigate {
+    pub fn new(config: &FrigateConfig) -> Self {
+        let prefix = config.topic_prefix.clone().unwrap_or_else(|| "frigate".to_string());
+        Frigate {
+            topics: vec![format!("{}/#", prefix)],
+            prefix,
+        }
+    }
+
+    fn event(&self, message: &Message, event: &Value) -> Vec<Point> {
+        let after = &event["after"];
+        if after["false_positive"].as_bool() == Some(true) {
+            return Vec::new();
+        }
+        let (Some(camera), Some(object)) = (after["camera"].as_str(), after["label"].as_str()) else {
+            return Vec::new();
+        };
+        let tags = [("camera", camera), ("object", object)];
+        let mut points = Vec::new();
+        match event["type"].as_str() {
+            Some("new") => points.push(point("frigate_detections", 1.0, &tags, message.received_at)),
+            Some("end") => {
+                if let Some(score) = after["top_score"].as_f64() {
+                    points.push(point("frigate_event_score", score, &tags, message.received_at));
+                }
+                if let (Some(start), Some(end)) = (after["start_time"].as_f64(), after["end_time"].as_f64()) {
+                    points.push(point("frigate_event_duration", end - start, &tags, message.received_at));
+                }
+            }
+            _ => {}
+        }
+        points
+    }
+
+    fn stats(&self, message: &Message, stats: &Value) -> Vec<Point> {
+        let mut points = Vec::new();
+        // Frigate 0.12 moved the cameras from the top level into `cameras`
+        let cameras = stats.get("cameras").unwrap_or(stats);
+        for (camera, camera_stats) in cameras.as_object().into_iter().flatten() {
+            for stat in CAMERA_STATS {
+                if let Some(value) = camera_stats[stat].as_f64() {
+                    let measurement = format!("frigate_{}", stat);
+                    points.push(point(&measurement, value, &[("camera", camera)], message.received_at));
+                }
+            }
+        }
+        for (detector, detector_stats) in stats["detectors"].as_object().into_iter().flatten() {
+            if let Some(speed) = detector_stats["inference_speed"].as_f64() {
+                points.push(point("frigate_inference_speed", speed, &[("detector", detector)], message.received_at));
+            }
+        }
+        if let Some(uptime) = stats["service"]["uptime"].as_f64() {
+            points.push(point("frigate_uptime", uptime, &[], message.received_at));
+        }
+        points
+    }
+}
+
+impl Preset for Frigate {
+    fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    fn extract(&self, message: &Message) -> Result<Vec<Point>> {
+        let Some(levels) = levels(&message.topic, &self.prefix) else {
+            return Ok(Vec::new());
+        };
+        Ok(match levels.as_slice() {
+            ["events"] => self.event(message, &serde_json::from_slice(&message.payload)?),
+            ["stats"] => self.stats(message, &serde_json::from_slice(&message.payload)?),
+            // current object counts per camera or zone, `all` counts every object
+            [camera, object] => match std::str::from_utf8(&message.payload).ok().and_then(|s| s.trim().parse::<f64>().ok()) {
+                Some(count) => vec![point("frigate_objects", count, &[("camera", camera), ("object", object)], message.received_at)],
+                // motion and other ON/OFF state topics
+                None => Vec::new(),
+            },
+            // snapshots, availability and per camera switches
+            _ => Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::Config;
+use crate::availability::Availability;
+use crate::pipeline::{Message, Point};
+use crate::topic_filter;
+
+mod evse;
+mod frigate;
+mod teslamate;
+mod theengs;
+mod weewx;
+
+/// Built-in translations for the MQTT topics of popular projects, enabled
+/// with a `[presets.<name>]` section.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PresetsConfig {
+    pub frigate: Option<frigate::FrigateConfig>,
+    pub openevse: Option<evse::OpenEvseConfig>,
+    pub goe: Option<evse::GoEConfig>,
+    pub teslamate: Option<teslamate::TeslaMateConfig>,
+    pub theengs: Option<theengs::TheengsConfig>,
+    pub weewx: Option<weewx::WeeWxConfig>,
+}
+
+/// Turns the messages of a well known project into points without any
+/// `[[measurements]]` configuration.
+pub trait Preset: Send + Sync {
+    /// Topic filters the bridge subscribes to for this preset
+    fn topics(&self) -> &[String];
+
+    /// Extracts the points from a message on one of the preset's topics.
+    fn extract(&self, message: &Message) -> Result<Vec<Point>>;
+
+    fn handles(&self, topic: &str) -> bool {
+        self.topics().iter().any(|filter| topic_filter::matches(filter, topic))
+    }
+}
+
+/// The enabled presets, along with availability tracking which is handled
+/// the same way.
+pub fn build(config: &Config) -> Vec<Box<dyn Preset>> {
+    let mut presets: Vec<Box<dyn Preset>> = Vec::new();
+    if let Some(availability) = &config.availability {
+        presets.push(Box::new(Availability::new(availability)));
+    }
+    let Some(config) = &config.presets else {
+        return presets;
+    };
+    if let Some(frigate) = &config.frigate {
+        presets.push(Box::new(frigate::Frigate::new(frigate)));
+    }
+    if let Some(openevse) = &config.openevse {
+        presets.push(Box::new(evse::OpenEvse::new(openevse)));
+    }
+    if let Some(goe) = &config.goe {
+        presets.push(Box::new(evse::GoE::new(goe)));
+    }
+    if let Some(teslamate) = &config.teslamate {
+        presets.push(Box::new(teslamate::TeslaMate::new(teslamate)));
+    }
+    if let Some(theengs) = &config.theengs {
+        presets.push(Box::new(theengs::Theengs::new(theengs)));
+    }
+    if let Some(weewx) = &config.weewx {
+        presets.push(Box::new(weewx::WeeWx::new(weewx)));
+    }
+    presets
+}
+
+pub fn point(measurement: &str, value: f64, tags: &[(&str, &str)], timestamp: DateTime<Utc>) -> Point {
+    let tags: HashMap<String, String> = tags
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    Point {
+        measurement: measurement.to_string(),
+        fields: vec![("value".to_string(), value.into())],
+        tags: (!tags.is_empty()).then_some(tags),
+        bucket: None,
+        sinks: None,
+        timestamp,
+    }
+}
+
+/// Strips `prefix/` from a topic, returning the remaining levels.
+fn levels<'a>(topic: &'a str, prefix: &str) -> Option<Vec<&'a str>> {
+    topic
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .map(|rest| rest.split('/').collect())
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::{Preset, levels, point};
+use crate::pipeline::{Message, Point};
+
+/// Codes the string states are written as, in the order of the TeslaMate docs
+const STATES: &[(&str, &[&str])] = &[
+    ("state", &["offline", "asleep", "suspended", "online", "driving", "charging", "updating"]),
+    ("shift_state", &["P", "R", "N", "D"]),
+    ("charging_state", &["Disconnected", "NoPower", "Stopped", "Starting", "Charging", "Complete"]),
+];
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TeslaMateConfig {
+    /// The MQTT namespace configured in TeslaMate, defaults to `teslamate`
+    pub topic_prefix: Option<String>,
+}
+
+/// TeslaMate: every `teslamate/cars/<id>/<name>` topic carries a bare value,
+/// written to `teslamate_<name>` tagged with the car ID.
+pub struct TeslaMate {
+    prefix: String,
+    topics: Vec<String>,
+}
+
+impl TeslaMate {
+    pub fn new(config: &TeslaMateConfig) -> Self {
+        let prefix = config.topic_prefix.clone().unwrap_or_else(|| "teslamate".to_string());
+        TeslaMate {
+            topics: vec![format!("{}/cars/+/+", prefix)],
+            prefix,
+        }
+    }
+}
+
+/// Numbers are used as is, booleans become 1 or 0 and known states their
+/// code. Free text such as the display name or geofence has no numeric form.
+fn value(name: &str, payload: &str) -> Option<f64> {
+    if let Ok(value) = payload.parse::<f64>() {
+        return Some(value);
+    }
+    match payload {
+        "true" => return Some(1.0),
+        "false" => return Some(0.0),
+        _ => {}
+    }
+    STATES
+        .iter()
+        .find(|(state, _)| *state == name)
+        .and_then(|(_, values)| values.iter().position(|value| *value == payload))
+        .map(|code| code as f64)
+}
+
+impl Preset for TeslaMate {
+    fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    fn extract(&self, message: &Message) -> Result<Vec<Point>> {
+        let Some(levels) = levels(&message.topic, &self.prefix) else {
+            return Ok(Vec::new());
+        };
+        let ["cars", car_id, name] = levels.as_slice() else {
+            return Ok(Vec::new());
+        };
+        // TeslaMate publishes an empty payload when a value is unknown
+        let payload = String::from_utf8_lossy(&message.payload);
+        let Some(value) = value(name, payload.trim()) else {
+            return Ok(Vec::new());
+        };
+        let measurement = format!("teslamate_{}", name);
+        Ok(vec![point(&measurement, value, &[("car_id", car_id)], message.received_at)])
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use super::{Preset, point};
+use crate::pipeline::{Message, Point};
+
+/// Decoded properties written, and the measurements they are written to
+const PROPERTIES: &[(&str, &str)] = &[("tempc", "ble_temperature"), ("hum", "ble_humidity"), ("batt", "ble_battery")];
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TheengsConfig {
+    /// The gateway's base topic, defaults to `home`
+    pub topic_prefix: Option<String>,
+}
+
+/// BLE sensors decoded by Theengs Gateway or OpenMQTTGateway, published as
+/// JSON on `home/<gateway>/BTtoMQTT/<mac>`.
+pub struct Theengs {
+    topics: Vec<String>,
+}
+
+impl Theengs {
+    pub fn new(config: &TheengsConfig) -> Self {
+        let prefix = config.topic_prefix.as_deref().unwrap_or("home");
+        Theengs {
+            topics: vec![format!("{}/+/BTtoMQTT/+", prefix)],
+        }
+    }
+}
+
+impl Preset for Theengs {
+    fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    fn extract(&self, message: &Message) -> Result<Vec<Point>> {
+        let decoded: serde_json::Value = serde_json::from_slice(&message.payload)
+            .map_err(|e| anyhow!("Failed to parse BLE advertisement on {}: {}", message.topic, e))?;
+        // advertisements the decoder did not recognise carry no model
+        let Some(model) = decoded["model_id"].as_str().or(decoded["model"].as_str()) else {
+            return Ok(Vec::new());
+        };
+        let mac = decoded["id"]
+            .as_str()
+            .or(message.topic.rsplit('/').next())
+            .unwrap_or_default();
+        Ok(PROPERTIES
+            .iter()
+            .filter_map(|(property, measurement)| {
+                let value = decoded[property].as_f64()?;
+                Some(point(measurement, value, &[("mac", mac), ("model", model)], message.received_at))
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use super::Preset;
+use crate::pipeline::{FieldValue, Message, Point};
+
+/// Which unit system fields are converted to before writing.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitSystem {
+    /// Write the units WeeWX publishes
+    #[default]
+    Keep,
+    Metric,
+    Us,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quantity {
+    Temperature,
+    Pressure,
+    Speed,
+    Rain,
+    RainRate,
+    Altitude,
+}
+
+/// Unit suffixes WeeWX appends to field names, with the scale and offset
+/// converting them to the first unit of their quantity.
+const UNITS: &[(&str, Quantity, f64, f64)] = &[
+    ("C", Quantity::Temperature, 1.0, 0.0),
+    ("F", Quantity::Temperature, 5.0 / 9.0, -160.0 / 9.0),
+    ("mbar", Quantity::Pressure, 1.0, 0.0),
+    ("hPa", Quantity::Pressure, 1.0, 0.0),
+    ("kPa", Quantity::Pressure, 10.0, 0.0),
+    ("inHg", Quantity::Pressure, 33.863_886, 0.0),
+    ("mmHg", Quantity::Pressure, 1.333_224, 0.0),
+    ("kph", Quantity::Speed, 1.0, 0.0),
+    ("mps", Quantity::Speed, 3.6, 0.0),
+    ("mph", Quantity::Speed, 1.609_344, 0.0),
+    ("knot", Quantity::Speed, 1.852, 0.0),
+    ("mm", Quantity::Rain, 1.0, 0.0),
+    ("cm", Quantity::Rain, 10.0, 0.0),
+    ("in", Quantity::Rain, 25.4, 0.0),
+    ("mm_per_hour", Quantity::RainRate, 1.0, 0.0),
+    ("cm_per_hour", Quantity::RainRate, 10.0, 0.0),
+    ("inch_per_hour", Quantity::RainRate, 25.4, 0.0),
+    ("meter", Quantity::Altitude, 1.0, 0.0),
+    ("foot", Quantity::Altitude, 0.3048, 0.0),
+];
+
+impl UnitSystem {
+    fn unit(self, quantity: Quantity) -> Option<&'static str> {
+        let unit = match (self, quantity) {
+            (UnitSystem::Keep, _) => return None,
+            (UnitSystem::Metric, Quantity::Temperature) => "C",
+            (UnitSystem::Metric, Quantity::Pressure) => "mbar",
+            (UnitSystem::Metric, Quantity::Speed) => "kph",
+            (UnitSystem::Metric, Quantity::Rain) => "mm",
+            (UnitSystem::Metric, Quantity::RainRate) => "mm_per_hour",
+            (UnitSystem::Metric, Quantity::Altitude) => "meter",
+            (UnitSystem::Us, Quantity::Temperature) => "F",
+            (UnitSystem::Us, Quantity::Pressure) => "inHg",
+            (UnitSystem::Us, Quantity::Speed) => "mph",
+            (UnitSystem::Us, Quantity::Rain) => "in",
+            (UnitSystem::Us, Quantity::RainRate) => "inch_per_hour",
+            (UnitSystem::Us, Quantity::Altitude) => "foot",
+        };
+        Some(unit)
+    }
+
+    /// Converts a field to this unit system, renaming its unit suffix to match.
+    fn convert(self, name: &str, value: f64) -> (String, FieldValue) {
+        let found = UNITS.iter().find_map(|unit| {
+            name.strip_suffix(unit.0)
+                .and_then(|base| base.strip_suffix('_'))
+                .map(|base| (base, unit))
+        });
+        let Some((base, &(_, quantity, scale, offset))) = found else {
+            return (name.to_string(), value.into());
+        };
+        let Some(target) = self.unit(quantity) else {
+            return (name.to_string(), value.into());
+        };
+        let Some(&(_, _, target_scale, target_offset)) = UNITS.iter().find(|unit| unit.0 == target) else {
+            return (name.to_string(), value.into());
+        };
+        let converted = (value * scale + offset - target_offset) / target_scale;
+        (format!("{}_{}", base, target), converted.into())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WeeWxConfig {
+    /// Topic of the loop packets, defaults to `weather/loop`
+    pub topic: Option<String>,
+    /// Measurement the fields are written to, defaults to `weather`
+    pub measurement: Option<String>,
+    pub units: Option<UnitSystem>,
+}
+
+/// WeeWX loop packets as published by the weewx-mqtt extension: a flat JSON
+/// object of `<observation>_<unit>` keys written as one multi-field point.
+pub struct WeeWx {
+    topics: Vec<String>,
+    measurement: String,
+    units: UnitSystem,
+}
+
+impl WeeWx {
+    pub fn new(config: &WeeWxConfig) -> Self {
+        WeeWx {
+            topics: vec![config.topic.clone().unwrap_or_else(|| "weather/loop".to_string())],
+            measurement: config.measurement.clone().unwrap_or_else(|| "weather".to_string()),
+            units: config.units.unwrap_or_default(),
+        }
+    }
+}
+
+/// Loop packets carry numbers either as JSON numbers or as strings.
+fn number(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(string) => string.parse().ok(),
+        _ => None,
+    }
+}
+
+impl Preset for WeeWx {
+    fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    fn extract(&self, message: &Message) -> Result<Vec<Point>> {
+        let packet: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&message.payload)
+            .map_err(|e| anyhow!("Failed to parse WeeWX packet on {}: {}", message.topic, e))?;
+        // the packet time is in epoch seconds
+        let timestamp = packet
+            .get("dateTime")
+            .and_then(number)
+            .and_then(|seconds| DateTime::<Utc>::from_timestamp_millis((seconds * 1000.0) as i64))
+            .unwrap_or(message.received_at);
+        let fields: Vec<(String, FieldValue)> = packet
+            .iter()
+            .filter(|(name, _)| !matches!(name.as_str(), "dateTime" | "usUnits" | "interval_minute"))
+            .filter_map(|(name, value)| number(value).map(|value| self.units.convert(name, value)))
+            .collect();
+        if fields.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(vec![Point {
+            measurement: self.measurement.clone(),
+            fields,
+            tags: None,
+            bucket: None,
+            sinks: None,
+            timestamp,
+        }])
+    }
+}
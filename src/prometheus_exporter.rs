@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::Router;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use log::{error, info};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::pipeline::{FieldValue, Point};
+use crate::sink::Sink;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PrometheusExporterConfig {
+    /// Address the metrics are served on, e.g. `0.0.0.0:9185`
+    pub listen: String,
+    /// Series not updated for this long are no longer served
+    pub max_age_secs: Option<u64>,
+}
+
+/// The latest value of a metric and when it arrived, by label set.
+type Series = BTreeMap<String, (f64, Instant)>;
+
+/// The latest value of every series, by metric name.
+pub struct LatestValues {
+    metrics: Mutex<BTreeMap<String, Series>>,
+    max_age: Option<Duration>,
+}
+
+impl LatestValues {
+    /// Records the numeric fields of a point as gauges named after the
+    /// measurement, followed by the field name unless it is `value`, with the
+    /// tags as labels. Text fields have no numeric value and are left out.
+    fn update(&self, point: &Point) {
+        let mut tags: Vec<_> = point.tags.iter().flatten().collect();
+        tags.sort();
+        let labels = tags
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", sanitize(key, false), escape(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let now = Instant::now();
+        let mut metrics = self.metrics.lock().unwrap();
+        for (field, value) in &point.fields {
+            let value = match value {
+                FieldValue::Float(value) => *value,
+                FieldValue::Integer(value) => *value as f64,
+                FieldValue::UInteger(value) => *value as f64,
+                FieldValue::Boolean(flag) => f64::from(u8::from(*flag)),
+                FieldValue::String(_) => continue,
+            };
+            let name = match field.as_str() {
+                "value" => sanitize(&point.measurement, true),
+                field => sanitize(&format!("{}_{}", point.measurement, field), true),
+            };
+            metrics.entry(name).or_default().insert(labels.clone(), (value, now));
+        }
+    }
+
+    /// Formats every series in the Prometheus text exposition format, first
+    /// forgetting those older than `max_age_secs`.
+    fn render(&self) -> String {
+        let mut metrics = self.metrics.lock().unwrap();
+        if let Some(max_age) = self.max_age {
+            for series in metrics.values_mut() {
+                series.retain(|_, (_, updated)| updated.elapsed() <= max_age);
+            }
+            metrics.retain(|_, series| !series.is_empty());
+        }
+        let mut out = String::new();
+        for (name, series) in metrics.iter() {
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            for (labels, (value, _)) in series {
+                match labels.as_str() {
+                    "" => {
+                        let _ = writeln!(out, "{} {}", name, number(*value));
+                    }
+                    labels => {
+                        let _ = writeln!(out, "{}{{{}}} {}", name, labels, number(*value));
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+#[async_trait]
+impl Sink for LatestValues {
+    async fn write_batch(&self, points: &[Point], _bucket: &str) -> Result<()> {
+        for point in points {
+            self.update(point);
+        }
+        Ok(())
+    }
+}
+
+/// Starts serving the latest values on `/metrics` of the configured address.
+pub async fn spawn(config: &PrometheusExporterConfig) -> Result<Arc<LatestValues>> {
+    let listen = &config.listen;
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", listen, e))?;
+    let values = Arc::new(LatestValues {
+        metrics: Mutex::new(BTreeMap::new()),
+        max_age: config.max_age_secs.map(Duration::from_secs),
+    });
+    let app = Router::new().route("/metrics", get(metrics)).with_state(values.clone());
+
+    info!("Prometheus exporter listening on {}", listen);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Prometheus exporter stopped: {}", e);
+        }
+    });
+    Ok(values)
+}
+
+async fn metrics(State(values): State<Arc<LatestValues>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], values.render())
+}
+
+/// Replaces characters Prometheus does not allow in metric names, which may
+/// also contain colons, or label names.
+fn sanitize(name: &str, metric: bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || (metric && c == ':') { c } else { '_' })
+        .collect();
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// A sample value, with infinities spelled the way Prometheus expects.
+fn number(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Result};
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, SerializeOptions};
+use std::fs;
+
+/// Field names as written in the `.proto` file, with every field present and
+/// 64-bit integers as numbers, so JSONPath sees the message as it was defined.
+const OPTIONS: SerializeOptions = SerializeOptions::new()
+    .use_proto_field_name(true)
+    .skip_default_fields(false)
+    .stringify_64_bit_integers(false);
+
+/// Loads the message types of compiled descriptor sets, as written by
+/// `protoc --include_imports --descriptor_set_out`.
+pub fn load_descriptors(paths: &[String]) -> Result<DescriptorPool> {
+    let mut pool = DescriptorPool::new();
+    for path in paths {
+        let bytes = fs::read(path).map_err(|e| anyhow!("Failed to read protobuf descriptors {}: {}", path, e))?;
+        pool.decode_file_descriptor_set(bytes.as_slice())
+            .map_err(|e| anyhow!("Failed to load protobuf descriptors {}: {}", path, e))?;
+    }
+    Ok(pool)
+}
+
+/// Looks up a message type by its full name, such as `telemetry.v1.Reading`.
+pub fn message(pool: &DescriptorPool, name: &str) -> Result<MessageDescriptor> {
+    pool.get_message_by_name(name)
+        .ok_or_else(|| anyhow!("Unknown protobuf message {}, check protobuf_descriptors", name))
+}
+
+/// Decodes a binary protobuf payload into its JSON representation.
+pub fn decode(message: &MessageDescriptor, payload: &[u8]) -> Result<serde_json::Value> {
+    let decoded = DynamicMessage::decode(message.clone(), payload)
+        .map_err(|e| anyhow!("Failed to decode {} payload: {}", message.full_name(), e))?;
+    Ok(decoded.serialize_with_options(serde_json::value::Serializer, &OPTIONS)?)
+}
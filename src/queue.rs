@@ -0,0 +1,143 @@
+use log::warn;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Notify;
+
+use crate::OverflowPolicy;
+use crate::metrics::METRICS;
+use crate::pipeline::Point;
+
+/// Returned when pushing to a queue whose consumer has stopped.
+#[derive(Debug)]
+pub struct QueueClosed;
+
+struct State {
+    points: VecDeque<Point>,
+    bytes: usize,
+    closed: bool,
+}
+
+/// The in-memory buffer between point extraction and the writer.
+///
+/// The queue is capped by point count and optionally by an approximate byte
+/// size. When full, producers either wait for space or, with `drop_oldest` or
+/// `drop_newest`, the oldest buffered or the incoming points are discarded so
+/// an extended InfluxDB outage cannot exhaust memory.
+pub struct WriteQueue {
+    state: Mutex<State>,
+    readable: Notify,
+    writable: Notify,
+    max_points: usize,
+    max_bytes: Option<usize>,
+    overflow: OverflowPolicy,
+    dropped: AtomicU64,
+}
+
+impl WriteQueue {
+    pub fn new(max_points: usize, max_bytes: Option<usize>, overflow: OverflowPolicy) -> Self {
+        WriteQueue {
+            state: Mutex::new(State {
+                points: VecDeque::new(),
+                bytes: 0,
+                closed: false,
+            }),
+            readable: Notify::new(),
+            writable: Notify::new(),
+            max_points: max_points.max(1),
+            max_bytes,
+            overflow,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn is_full(&self, state: &State, incoming: usize) -> bool {
+        !state.points.is_empty()
+            && (state.points.len() >= self.max_points
+                || self.max_bytes.is_some_and(|max| state.bytes + incoming > max))
+    }
+
+    pub async fn push(&self, point: Point) -> Result<(), QueueClosed> {
+        let size = point.approximate_size();
+        loop {
+            let writable = self.writable.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return Err(QueueClosed);
+                }
+                if self.overflow == OverflowPolicy::DropOldest {
+                    let mut dropped = 0u64;
+                    while self.is_full(&state, size) {
+                        if let Some(oldest) = state.points.pop_front() {
+                            let oldest_size = oldest.approximate_size();
+                            state.bytes -= oldest_size;
+                            METRICS.queued_points.fetch_sub(1, Ordering::Relaxed);
+                            METRICS.queued_bytes.fetch_sub(oldest_size, Ordering::Relaxed);
+                            dropped += 1;
+                        }
+                    }
+                    self.count_dropped(dropped, "oldest");
+                }
+                if !self.is_full(&state, size) {
+                    state.bytes += size;
+                    state.points.push_back(point);
+                    METRICS.queued_points.fetch_add(1, Ordering::Relaxed);
+                    METRICS.queued_bytes.fetch_add(size, Ordering::Relaxed);
+                    self.readable.notify_one();
+                    return Ok(());
+                }
+                if self.overflow == OverflowPolicy::DropNewest {
+                    self.count_dropped(1, "newest");
+                    return Ok(());
+                }
+            }
+            writable.await;
+        }
+    }
+
+    fn count_dropped(&self, dropped: u64, which: &str) {
+        if dropped == 0 {
+            return;
+        }
+        METRICS.dropped_points.fetch_add(dropped, Ordering::Relaxed);
+        let total = self.dropped.fetch_add(dropped, Ordering::Relaxed) + dropped;
+        // log the first drop and then periodically, not once per point
+        if total == dropped || total / 1000 != (total - dropped) / 1000 {
+            warn!("Write queue full, dropped {} {} points so far", total, which);
+        }
+    }
+
+    /// Waits for the next point, returning `None` once the queue is closed.
+    pub async fn pop(&self) -> Option<Point> {
+        loop {
+            let readable = self.readable.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(point) = state.points.pop_front() {
+                    let size = point.approximate_size();
+                    state.bytes -= size;
+                    METRICS.queued_points.fetch_sub(1, Ordering::Relaxed);
+                    METRICS.queued_bytes.fetch_sub(size, Ordering::Relaxed);
+                    self.writable.notify_waiters();
+                    return Some(point);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+
+    /// Stops the queue, failing any further pushes.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_waiters();
+        self.writable.notify_waiters();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().points.len()
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, Utc};
+use rumqttc::Publish;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+
+/// A single MQTT publish as stored in a recording file, one JSON object per line.
+///
+/// Payloads that are valid UTF-8 are stored verbatim in `payload`, anything else
+/// is stored base64 encoded in `payload_base64` so binary traffic survives the
+/// round trip.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecordedMessage {
+    pub timestamp: DateTime<Utc>,
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_base64: Option<String>,
+}
+
+impl RecordedMessage {
+    pub fn from_publish(publish: &Publish, timestamp: DateTime<Utc>) -> Self {
+        let (payload, payload_base64) = match std::str::from_utf8(&publish.payload) {
+            Ok(s) => (Some(s.to_string()), None),
+            Err(_) => (None, Some(BASE64.encode(&publish.payload))),
+        };
+        RecordedMessage {
+            timestamp,
+            topic: publish.topic.clone(),
+            qos: publish.qos as u8,
+            retain: publish.retain,
+            payload,
+            payload_base64,
+        }
+    }
+
+    pub fn payload_bytes(&self) -> Result<Vec<u8>> {
+        match (&self.payload, &self.payload_base64) {
+            (Some(payload), _) => Ok(payload.as_bytes().to_vec()),
+            (None, Some(encoded)) => BASE64
+                .decode(encoded)
+                .map_err(|e| anyhow!("Invalid base64 payload for topic {}: {}", self.topic, e)),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Appends received messages to a newline-delimited JSON file.
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open recording file {}: {}", path, e))?;
+        Ok(Recorder {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn record(&mut self, message: &RecordedMessage) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, message)?;
+        self.writer.write_all(b"\n")?;
+        // flush every line so a killed recorder still leaves a usable file
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads messages back from a file written by [`Recorder`].
+pub struct RecordingReader {
+    lines: Lines<BufReader<File>>,
+    line_number: usize,
+}
+
+impl RecordingReader {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path).map_err(|e| anyhow!("Failed to open recording file {}: {}", path, e))?;
+        Ok(RecordingReader {
+            lines: BufReader::new(file).lines(),
+            line_number: 0,
+        })
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = Result<RecordedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(
+                serde_json::from_str(&line)
+                    .map_err(|e| anyhow!("Invalid recorded message on line {}: {}", self.line_number, e)),
+            );
+        }
+    }
+}
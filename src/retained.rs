@@ -0,0 +1,69 @@
+use rumqttc::Publish;
+use serde::Deserialize;
+
+use crate::Config;
+use crate::dedup::message_key;
+use crate::state::STATE;
+use crate::topic_filter;
+
+/// What happens to retained messages, which the broker sends on every
+/// subscribe and which would otherwise be written again after each restart.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetainedPolicy {
+    /// Process retained messages like any other
+    #[default]
+    Accept,
+    /// Drop every retained message
+    Skip,
+    /// Process a retained message only the first time it is seen, remembered
+    /// across restarts with `state_file`
+    Once,
+}
+
+/// Applies the retained policies of the subscriptions to incoming publishes.
+pub struct RetainedFilter {
+    /// `mqtt_topics` entries with their own policy, in configuration order
+    topics: Vec<(String, RetainedPolicy)>,
+    default: RetainedPolicy,
+}
+
+impl RetainedFilter {
+    pub fn new(config: &Config) -> Self {
+        RetainedFilter {
+            topics: config
+                .mqtt_topics
+                .iter()
+                .flatten()
+                .filter_map(|topic| topic.retained.map(|policy| (topic.topic.clone(), policy)))
+                .collect(),
+            default: config.mqtt_retained.unwrap_or_default(),
+        }
+    }
+
+    /// Whether a publish should be processed. Only publishes with the retain
+    /// bit set are affected, which brokers set on stored messages sent at
+    /// subscribe time but not on live ones.
+    pub fn accept(&self, publish: &Publish) -> bool {
+        if !publish.retain {
+            return true;
+        }
+        let policy = self
+            .topics
+            .iter()
+            .find(|(filter, _)| topic_filter::matches(filter, &publish.topic))
+            .map_or(self.default, |(_, policy)| *policy);
+        match policy {
+            RetainedPolicy::Accept => true,
+            RetainedPolicy::Skip => false,
+            RetainedPolicy::Once => {
+                let key = message_key(&publish.topic, &publish.payload);
+                STATE.update("retained", &publish.topic, |last| {
+                    let seen = last.as_ref().and_then(|last| last.as_u64()) == Some(key);
+                    *last = Some(key.into());
+                    !seen
+                })
+            }
+        }
+    }
+}
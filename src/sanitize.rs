@@ -0,0 +1,79 @@
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::pipeline::{FieldValue, Point};
+
+/// How measurement names and tags are cleaned before they are written.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SanitizeMode {
+    /// Write names and tags exactly as configured or extracted
+    None,
+    /// Replace line breaks, which line protocol cannot escape, and drop empty
+    /// tags; spaces, commas and equals signs are escaped when writing
+    #[default]
+    Escape,
+    /// Additionally replace spaces, commas and equals signs
+    Replace,
+}
+
+pub struct Sanitizer {
+    mode: SanitizeMode,
+    replacement: String,
+}
+
+impl Sanitizer {
+    pub fn new(mode: SanitizeMode, replacement: Option<&str>) -> Self {
+        Sanitizer {
+            mode,
+            replacement: replacement.unwrap_or("_").to_string(),
+        }
+    }
+
+    fn clean<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let special = |c: char| match self.mode {
+            SanitizeMode::None => false,
+            SanitizeMode::Escape => c == '\n' || c == '\r',
+            SanitizeMode::Replace => matches!(c, '\n' | '\r' | ' ' | ',' | '='),
+        };
+        if !value.contains(special) {
+            return Cow::Borrowed(value);
+        }
+        let mut cleaned = String::with_capacity(value.len());
+        for c in value.chars() {
+            if special(c) {
+                cleaned.push_str(&self.replacement);
+            } else {
+                cleaned.push(c);
+            }
+        }
+        Cow::Owned(cleaned)
+    }
+
+    pub fn apply(&self, point: &mut Point) {
+        if self.mode == SanitizeMode::None {
+            return;
+        }
+        if let Cow::Owned(measurement) = self.clean(&point.measurement) {
+            point.measurement = measurement;
+        }
+        if let Some(tags) = point.tags.take() {
+            let tags: HashMap<String, String> = tags
+                .into_iter()
+                .map(|(key, value)| (self.clean(&key).into_owned(), self.clean(&value).into_owned()))
+                // line protocol has no way to express an empty tag key or value
+                .filter(|(key, value)| !key.is_empty() && !value.is_empty())
+                .collect();
+            point.tags = (!tags.is_empty()).then_some(tags);
+        }
+        // quoted string fields only need their line breaks replaced
+        for (_, value) in &mut point.fields {
+            if let FieldValue::String(text) = value
+                && text.contains(['\n', '\r'])
+            {
+                *text = text.replace(['\n', '\r'], &self.replacement);
+            }
+        }
+    }
+}
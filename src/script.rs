@@ -0,0 +1,87 @@
+#[cfg(not(feature = "rhai"))]
+use anyhow::Result;
+
+#[cfg(not(feature = "rhai"))]
+use crate::pipeline::Message;
+
+#[cfg(not(feature = "rhai"))]
+pub struct Script;
+
+#[cfg(not(feature = "rhai"))]
+impl Script {
+    pub fn load(_path: &str) -> Result<Self> {
+        Err(anyhow::anyhow!("A script is configured but this build lacks the rhai feature"))
+    }
+
+    pub fn run(&self, _payload: &serde_json::Value, _message: &Message) -> Result<Vec<serde_json::Value>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(feature = "rhai")]
+pub use enabled::Script;
+
+#[cfg(feature = "rhai")]
+mod enabled {
+    use anyhow::{anyhow, Result};
+    use rhai::{Dynamic, Engine, Scope, AST};
+    use std::sync::{Arc, OnceLock};
+
+    use crate::pipeline::Message;
+
+    /// The most operations a script may run per message, so one stuck in a
+    /// loop fails instead of stalling its worker.
+    const MAX_OPERATIONS: u64 = 1_000_000;
+
+    /// A Rhai script turning a decoded payload into points.
+    pub struct Script {
+        path: String,
+        engine: Arc<Engine>,
+        ast: AST,
+    }
+
+    /// The engine every script runs on.
+    fn engine() -> Arc<Engine> {
+        static ENGINE: OnceLock<Arc<Engine>> = OnceLock::new();
+        ENGINE
+            .get_or_init(|| {
+                let mut engine = Engine::new();
+                engine.set_max_operations(MAX_OPERATIONS);
+                Arc::new(engine)
+            })
+            .clone()
+    }
+
+    impl Script {
+        pub fn load(path: &str) -> Result<Self> {
+            let engine = engine();
+            let ast = engine.compile_file(path.into()).map_err(|e| anyhow!("Failed to load script {}: {}", path, e))?;
+            Ok(Script { path: path.to_string(), engine, ast })
+        }
+
+        /// Runs the script with the payload as `payload` and the message's
+        /// `topic`, `retain` and `qos`, returning what it produced: nothing for
+        /// `()`, every element of an array, or the single value otherwise.
+        pub fn run(&self, payload: &serde_json::Value, message: &Message) -> Result<Vec<serde_json::Value>> {
+            let mut scope = Scope::new();
+            let payload = rhai::serde::to_dynamic(payload).map_err(|e| anyhow!("{}: {}", self.path, e))?;
+            scope.push_constant("payload", payload);
+            scope.push_constant("topic", message.topic.clone());
+            scope.push_constant("retain", message.retain);
+            scope.push_constant("qos", i64::from(message.qos));
+            let result: Dynamic = self
+                .engine
+                .eval_ast_with_scope(&mut scope, &self.ast)
+                .map_err(|e| anyhow!("Script {} failed: {}", self.path, e))?;
+            if result.is_unit() {
+                return Ok(Vec::new());
+            }
+            let result: serde_json::Value = rhai::serde::from_dynamic(&result)
+                .map_err(|e| anyhow!("Script {} returned an unsupported value: {}", self.path, e))?;
+            Ok(match result {
+                serde_json::Value::Array(items) => items,
+                item => vec![item],
+            })
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use log::{debug, error};
+use rand::Rng;
+use rumqttc::{AsyncClient, QoS};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SimulatorConfig {
+    pub topic: String,
+    /// Payload template, `{name}` placeholders are replaced with generated values
+    pub template: String,
+    pub interval_ms: Option<u64>,
+    pub values: Option<HashMap<String, ValueRange>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64,
+    pub integer: Option<bool>,
+}
+
+impl SimulatorConfig {
+    pub fn render(&self) -> String {
+        let mut rng = rand::rng();
+        let mut payload = self.template.clone();
+        if let Some(values) = &self.values {
+            for (name, range) in values {
+                let value = if range.min < range.max {
+                    rng.random_range(range.min..=range.max)
+                } else {
+                    range.min
+                };
+                let rendered = if range.integer.unwrap_or(false) {
+                    (value.round() as i64).to_string()
+                } else {
+                    value.to_string()
+                };
+                payload = payload.replace(&format!("{{{}}}", name), &rendered);
+            }
+        }
+        payload
+    }
+
+    /// Publishes rendered payloads every `interval_ms` until `count` messages
+    /// have been sent, or forever when no count is given.
+    pub async fn run(self, client: AsyncClient, count: Option<u64>) -> Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_millis(self.interval_ms.unwrap_or(1000).max(1)));
+        let mut sent = 0u64;
+        while count.is_none_or(|c| sent < c) {
+            interval.tick().await;
+            let payload = self.render();
+            debug!("Simulating message on {}: {}", self.topic, payload);
+            if let Err(e) = client.publish(&self.topic, QoS::AtLeastOnce, false, payload).await {
+                error!("Error publishing simulated message to {}: {}", self.topic, e);
+                return Err(e.into());
+            }
+            sent += 1;
+        }
+        Ok(())
+    }
+}
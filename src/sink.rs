@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::influx::line_protocol;
+use crate::pipeline::Point;
+use crate::topic_filter;
+
+/// Somewhere points are written: an InfluxDB server or one of the other
+/// outputs. Each configured sink gets a `PointWriter` of its own, which adds
+/// the queueing, retries, dead letters and disk buffer on top.
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Writes points to one bucket in a single request. Sinks without buckets
+    /// ignore it.
+    async fn write_batch(&self, points: &[Point], bucket: &str) -> Result<()>;
+
+    async fn write(&self, point: &Point, bucket: &str) -> Result<()> {
+        self.write_batch(std::slice::from_ref(point), bucket).await
+    }
+
+    /// Checks that the sink is up and answering requests.
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether a failed write may succeed when retried, rather than being
+    /// refused the same way every time.
+    fn is_retryable(&self, _error: &anyhow::Error) -> bool {
+        true
+    }
+}
+
+/// Prints line protocol to stdout instead of writing anywhere.
+pub struct DryRun;
+
+#[async_trait]
+impl Sink for DryRun {
+    async fn write_batch(&self, points: &[Point], _bucket: &str) -> Result<()> {
+        println!("{}", points.iter().map(line_protocol).collect::<Vec<_>>().join("\n"));
+        Ok(())
+    }
+}
+
+/// Formats line protocol and throws it away, used to benchmark the pipeline.
+pub struct Discard;
+
+#[async_trait]
+impl Sink for Discard {
+    async fn write_batch(&self, points: &[Point], _bucket: &str) -> Result<()> {
+        for point in points {
+            std::hint::black_box(line_protocol(point));
+        }
+        Ok(())
+    }
+}
+
+/// An entry of the `[[sink_routes]]` table, sending the points of matching
+/// measurements to some of the sinks only.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SinkRouteConfig {
+    /// A topic filter the message must have arrived on
+    pub topic: Option<String>,
+    /// The name the point must be written with, after any substitutions
+    pub measurement: Option<String>,
+    pub sinks: Vec<String>,
+}
+
+/// Decides which sinks every point goes to: those of its measurement's
+/// `sinks`, otherwise those of the first matching route, otherwise all.
+pub struct SinkRoutes {
+    routes: Vec<(SinkRouteConfig, Arc<[String]>)>,
+}
+
+impl SinkRoutes {
+    /// Checks every route only names sinks in `known`.
+    pub fn new(routes: Option<&[SinkRouteConfig]>, known: &[String]) -> Result<Self> {
+        let mut checked = Vec::new();
+        for route in routes.into_iter().flatten() {
+            checked.push((route.clone(), sinks(&route.sinks, known, "sink_routes entry")?));
+        }
+        Ok(SinkRoutes { routes: checked })
+    }
+
+    /// The sinks of the first route matching a point, or None for every sink.
+    pub fn route(&self, topic: &str, measurement: &str) -> Option<Arc<[String]>> {
+        self.routes
+            .iter()
+            .find(|(route, _)| {
+                route.topic.as_deref().is_none_or(|filter| topic_filter::matches(filter, topic))
+                    && route.measurement.as_deref().is_none_or(|name| name == measurement)
+            })
+            .map(|(_, sinks)| sinks.clone())
+    }
+}
+
+/// Checks a list of sink names against the configured sinks, for `what`.
+pub fn sinks(names: &[String], known: &[String], what: &str) -> Result<Arc<[String]>> {
+    if let Some(unknown) = names.iter().find(|name| !known.contains(name)) {
+        return Err(anyhow!("The {} refers to unknown sink {}, expected one of {}", what, unknown, known.join(", ")));
+    }
+    Ok(names.into())
+}
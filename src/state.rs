@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Values that stateful processing carries from one message to the next, such
+/// as the last event written or a counter's previous reading. Grouped into
+/// named sections and optionally persisted so a restart does not lose them.
+pub struct State {
+    sections: Mutex<BTreeMap<String, BTreeMap<String, Value>>>,
+    dirty: AtomicBool,
+}
+
+pub static STATE: State = State {
+    sections: Mutex::new(BTreeMap::new()),
+    dirty: AtomicBool::new(false),
+};
+
+impl State {
+    /// Runs `f` on the value stored for `key` in `section`, which it may
+    /// replace or remove by assigning `None`.
+    pub fn update<R>(&self, section: &str, key: &str, f: impl FnOnce(&mut Option<Value>) -> R) -> R {
+        let mut sections = self.sections.lock().unwrap();
+        let values = sections.entry(section.to_string()).or_default();
+        let mut value = values.get(key).cloned();
+        let before = value.clone();
+        let result = f(&mut value);
+        if value != before {
+            match value {
+                Some(value) => values.insert(key.to_string(), value),
+                None => values.remove(key),
+            };
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Restores the state saved by a previous run. A missing file is a first
+    /// start, an unreadable one is reported and replaced on the next save.
+    pub fn load(&self, path: &str) {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                warn!("Failed to read state file {}, starting without saved state: {}", path, e);
+                return;
+            }
+        };
+        match serde_json::from_str(&content) {
+            Ok(sections) => {
+                *self.sections.lock().unwrap() = sections;
+                info!("Restored pipeline state from {}", path);
+            }
+            Err(e) => warn!("Failed to parse state file {}, starting without saved state: {}", path, e),
+        }
+    }
+
+    /// Writes the state if it changed since the last save, replacing the file
+    /// atomically so a crash mid-write cannot corrupt it.
+    pub fn save(&self, path: &str) -> Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let content = serde_json::to_string(&*self.sections.lock().unwrap())?;
+        let temporary = format!("{}.tmp", path);
+        fs::write(&temporary, content).map_err(|e| anyhow!("Failed to write state file {}: {}", temporary, e))?;
+        fs::rename(&temporary, path).map_err(|e| anyhow!("Failed to replace state file {}: {}", path, e))?;
+        Ok(())
+    }
+}
+
+/// Loads the state file and saves it every `interval` and on Ctrl-C, after
+/// which the process exits.
+pub fn spawn(path: &str, interval: Duration) {
+    STATE.load(path);
+    let path = path.to_string();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if let Err(e) = STATE.save(&path) {
+                        error!("{}", e);
+                    }
+                }
+                _ = tokio::signal::ctrl_c() => {
+                    if let Err(e) = STATE.save(&path) {
+                        error!("{}", e);
+                    }
+                    info!("Saved pipeline state to {}, shutting down", path);
+                    std::process::exit(0);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// How payload timestamps are written.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// Numbers are Unix seconds, text is RFC 3339 or a local time
+    #[default]
+    Auto,
+    /// Text only, RFC 3339 or a local time
+    Rfc3339,
+    /// Unix times in the given unit, as numbers or numeric text
+    UnixS,
+    UnixMs,
+    UnixUs,
+    UnixNs,
+}
+
+impl TimestampFormat {
+    /// Nanoseconds per unit of a Unix timestamp in this format.
+    fn unit_nanos(self) -> Option<i64> {
+        match self {
+            TimestampFormat::Auto | TimestampFormat::UnixS => Some(1_000_000_000),
+            TimestampFormat::UnixMs => Some(1_000_000),
+            TimestampFormat::UnixUs => Some(1_000),
+            TimestampFormat::UnixNs => Some(1),
+            TimestampFormat::Rfc3339 => None,
+        }
+    }
+}
+
+/// Layouts accepted for timestamps that carry no UTC offset.
+const NAIVE_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y/%m/%d %H:%M:%S%.f"];
+
+pub fn parse_timezone(name: &str) -> Result<Tz> {
+    Tz::from_str(name).map_err(|e| anyhow!("Invalid timezone {}: {}", name, e))
+}
+
+/// Parses a payload timestamp. Unix times are numbers, or numeric text for
+/// the `unix_*` formats, RFC 3339 values carry their own offset, and values
+/// without one (e.g. `2024-05-01 14:00:00`) are taken as local time in `timezone`.
+pub fn parse_timestamp(value: &serde_json::Value, format: TimestampFormat, timezone: Tz) -> Result<DateTime<Utc>> {
+    let text = value.as_str().map(str::trim);
+    if let Some(unit_nanos) = format.unit_nanos() {
+        let number = match value {
+            serde_json::Value::Number(number) => Some(number.clone()),
+            _ if format != TimestampFormat::Auto => text.and_then(|text| serde_json::Number::from_str(text).ok()),
+            _ => None,
+        };
+        if let Some(number) = number {
+            return unix_timestamp(&number, unit_nanos).ok_or_else(|| anyhow!("Timestamp {} is out of range", number));
+        }
+    }
+    let text = text.ok_or_else(|| anyhow!("Unsupported timestamp value {}", value))?;
+
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    let naive = NAIVE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .ok_or_else(|| anyhow!("Unrecognised timestamp {}", text))?;
+    // during a DST fold the earlier instant wins, times skipped by DST are rejected
+    timezone
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok_or_else(|| anyhow!("Timestamp {} does not exist in {}", text, timezone))
+}
+
+fn unix_timestamp(number: &serde_json::Number, unit_nanos: i64) -> Option<DateTime<Utc>> {
+    // integers are converted exactly, fractions to the nearest nanosecond
+    let nanos = match number.as_i64() {
+        Some(units) => units.checked_mul(unit_nanos)?,
+        None => {
+            let nanos = (number.as_f64()? * unit_nanos as f64).round();
+            if !(i64::MIN as f64..i64::MAX as f64).contains(&nanos) {
+                return None;
+            }
+            nanos as i64
+        }
+    };
+    Some(DateTime::from_timestamp_nanos(nanos))
+}
@@ -0,0 +1,52 @@
+/// Whether an MQTT topic filter, which may contain `+` and `#` wildcards,
+/// matches a topic. A `+` may be followed by a name, as in `sensors/+device/temp`,
+/// to capture that level with [`captures`].
+pub fn matches(filter: &str, topic: &str) -> bool {
+    let filter = without_share(filter);
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            (_, None) => return false,
+            (level, Some(_)) if level.starts_with('+') => {}
+            (level, Some(topic_level)) if level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// The topic levels matched by the named `+` wildcards of a filter, paired
+/// with their names. Only meaningful for a topic the filter [`matches`].
+pub fn captures<'a>(filter: &'a str, topic: &'a str) -> Vec<(&'a str, &'a str)> {
+    without_share(filter)
+        .split('/')
+        .zip(topic.split('/'))
+        .filter_map(|(level, topic_level)| {
+            let name = level.strip_prefix('+')?;
+            (!name.is_empty()).then_some((name, topic_level))
+        })
+        .collect()
+}
+
+/// The filter with any wildcard names removed, as the broker expects it.
+pub fn subscription(filter: &str) -> String {
+    filter
+        .split('/')
+        .map(|level| if level.starts_with('+') { "+" } else { level })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Splits a shared subscription, `$share/<group>/<filter>`, into its group and
+/// the filter the publishes it receives match.
+pub fn shared(filter: &str) -> Option<(&str, &str)> {
+    let (group, filter) = filter.strip_prefix("$share/")?.split_once('/')?;
+    let valid = !group.is_empty() && !group.contains(['+', '#']) && !filter.is_empty();
+    valid.then_some((group, filter))
+}
+
+/// The filter without the prefix of a shared subscription, if it has one.
+pub fn without_share(filter: &str) -> &str {
+    shared(filter).map_or(filter, |(_, filter)| filter)
+}
@@ -0,0 +1,82 @@
+use anyhow::{Error, Result};
+use log::warn;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc;
+
+use crate::metrics::METRICS;
+use crate::pipeline::{Message, Pipeline};
+use crate::topic_filter;
+use crate::workers::WorkerPool;
+use crate::writer::{PointWriter, Writers};
+use crate::{Config, OverflowPolicy};
+
+/// Runs an independent decode, extract and write pipeline per subscribed
+/// topic filter.
+///
+/// Every filter gets its own worker and writers with their own buffers, so one
+/// noisy topic (or one whose writes are slow) cannot starve the rest. Messages
+/// matching none of the filters, such as those of the HTTP source, share one
+/// more pipeline. Keying by filter rather than by publish topic keeps the
+/// number of pipelines fixed under wildcard subscriptions.
+pub struct TopicPipelines {
+    /// The filters in configuration order, each with its worker and writers
+    pipelines: Vec<(String, WorkerPool, Arc<Writers>)>,
+    other: (WorkerPool, Arc<Writers>),
+    overflow: OverflowPolicy,
+}
+
+impl TopicPipelines {
+    pub fn new(
+        filters: Vec<String>,
+        pipeline: Arc<Pipeline>,
+        config: Arc<Config>,
+        point_writers: Vec<Arc<PointWriter>>,
+        fatal: mpsc::Sender<Error>,
+    ) -> Self {
+        let spawn = || {
+            let points = Writers::spawn(point_writers.clone(), config.clone(), fatal.clone());
+            let pool = WorkerPool::spawn(1, pipeline.clone(), config.clone(), points.clone(), fatal.clone());
+            (pool, points)
+        };
+        let pipelines = filters
+            .into_iter()
+            .map(|filter| {
+                let (pool, points) = spawn();
+                (filter, pool, points)
+            })
+            .collect();
+        TopicPipelines { pipelines, other: spawn(), overflow: config.buffer_overflow.unwrap_or_default() }
+    }
+
+    /// Queues a message on the pipeline of the first filter it matches. When
+    /// that pipeline is full it waits with `buffer_overflow = "block"`, and
+    /// otherwise drops the message.
+    pub async fn dispatch(&self, message: Message) -> Result<()> {
+        let (filter, pool) = self
+            .pipelines
+            .iter()
+            .find(|(filter, _, _)| topic_filter::matches(filter, &message.topic))
+            .map_or((None, &self.other.0), |(filter, pool, _)| (Some(filter), pool));
+        if self.overflow == OverflowPolicy::Block {
+            return pool.dispatch(message).await;
+        }
+        if let Some(message) = pool.try_dispatch(message)? {
+            METRICS.dropped_messages.fetch_add(1, Ordering::Relaxed);
+            let filter = filter.map_or("other topics", String::as_str);
+            warn!("Pipeline for {} is full, dropping message on {}", filter, message.topic);
+        }
+        Ok(())
+    }
+
+    /// Number of points waiting for the writers of the pipeline furthest behind.
+    pub fn len(&self) -> usize {
+        self.pipelines
+            .iter()
+            .map(|(_, _, points)| points)
+            .chain([&self.other.1])
+            .map(|points| points.len())
+            .max()
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use chrono::Utc;
+use log::{debug, error, info};
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+use crate::pipeline::Message;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct UdpSourceConfig {
+    pub listen: String,
+    /// Topic the datagrams are treated as published on, defaults to `udp`
+    pub topic: Option<String>,
+    pub format: Option<UdpFormat>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UdpFormat {
+    /// Every datagram is one JSON payload
+    #[default]
+    Json,
+    /// Datagrams hold `name:value|type` lines, turned into a JSON object of
+    /// metric names to values
+    Statsd,
+}
+
+/// Starts a UDP listener feeding every datagram into `messages` as if it had
+/// been published over MQTT.
+pub async fn spawn(config: &UdpSourceConfig, messages: mpsc::Sender<Message>) -> Result<()> {
+    let socket = UdpSocket::bind(&config.listen)
+        .await
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", config.listen, e))?;
+    let topic = config.topic.clone().unwrap_or_else(|| "udp".to_string());
+    let format = config.format.unwrap_or_default();
+
+    info!("UDP source listening on {}", config.listen);
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; 65536];
+        loop {
+            let length = match socket.recv(&mut buffer).await {
+                Ok(length) => length,
+                Err(e) => {
+                    error!("Error receiving on the UDP source: {}", e);
+                    continue;
+                }
+            };
+            let payload = match format {
+                UdpFormat::Json => Bytes::copy_from_slice(&buffer[..length]),
+                UdpFormat::Statsd => match statsd_to_json(&buffer[..length]) {
+                    Some(payload) => payload,
+                    None => continue,
+                },
+            };
+            let message = Message {
+                topic: topic.clone(),
+                payload,
+                qos: 0,
+                retain: false,
+                received_at: Utc::now(),
+            };
+            if messages.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Converts statsd lines such as `boiler.temp:61.5|g` into `{"boiler.temp": 61.5}`.
+/// Sampled counters are scaled back up by their sample rate, lines that cannot
+/// be parsed are skipped.
+fn statsd_to_json(datagram: &[u8]) -> Option<Bytes> {
+    let text = String::from_utf8_lossy(datagram);
+    let mut metrics = Map::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let parsed = line.split_once(':').and_then(|(name, rest)| {
+            let mut fields = rest.split('|');
+            let mut value = fields.next()?.parse::<f64>().ok()?;
+            let kind = fields.next()?;
+            if kind == "c"
+                && let Some(rate) = fields.find_map(|field| field.strip_prefix('@'))
+                && let Ok(rate) = rate.parse::<f64>()
+                && rate > 0.0
+            {
+                value /= rate;
+            }
+            Some((name.to_string(), Number::from_f64(value)?))
+        });
+        match parsed {
+            Some((name, value)) => {
+                metrics.insert(name, Value::Number(value));
+            }
+            None => debug!("Skipping invalid statsd line {}", line),
+        }
+    }
+    (!metrics.is_empty()).then(|| Bytes::from(Value::Object(metrics).to_string()))
+}
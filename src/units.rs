@@ -0,0 +1,63 @@
+use evalexpr::{ContextWithMutableFunctions, EvalexprError, EvalexprResult, Function, HashMapContext, Node, Value};
+
+/// Unit conversions available to expressions, each a factor and an offset:
+/// `f_to_c(value)` is `value * factor + offset`.
+const CONVERSIONS: [(&str, f64, f64); 24] = [
+    ("f_to_c", 5.0 / 9.0, -160.0 / 9.0),
+    ("c_to_f", 1.8, 32.0),
+    ("k_to_c", 1.0, -273.15),
+    ("c_to_k", 1.0, 273.15),
+    ("psi_to_kpa", 6.894757, 0.0),
+    ("kpa_to_psi", 1.0 / 6.894757, 0.0),
+    ("bar_to_kpa", 100.0, 0.0),
+    ("inhg_to_hpa", 33.863886, 0.0),
+    ("hpa_to_inhg", 1.0 / 33.863886, 0.0),
+    ("mph_to_ms", 0.44704, 0.0),
+    ("ms_to_mph", 1.0 / 0.44704, 0.0),
+    ("kmh_to_ms", 1.0 / 3.6, 0.0),
+    ("ms_to_kmh", 3.6, 0.0),
+    ("knots_to_ms", 1852.0 / 3600.0, 0.0),
+    ("in_to_mm", 25.4, 0.0),
+    ("mm_to_in", 1.0 / 25.4, 0.0),
+    ("ft_to_m", 0.3048, 0.0),
+    ("m_to_ft", 1.0 / 0.3048, 0.0),
+    ("mi_to_km", 1.609344, 0.0),
+    ("km_to_mi", 1.0 / 1.609344, 0.0),
+    ("gal_to_l", 3.785411784, 0.0),
+    ("l_to_gal", 1.0 / 3.785411784, 0.0),
+    ("lb_to_kg", 0.45359237, 0.0),
+    ("kg_to_lb", 1.0 / 0.45359237, 0.0),
+];
+
+/// Adds the helpers an expression calls to the context it is evaluated in,
+/// leaving out the others so evaluating stays cheap.
+pub fn install(expression: &Node, context: &mut HashMapContext) -> EvalexprResult<()> {
+    for name in expression.iter_function_identifiers() {
+        if let Some(function) = helper(name) {
+            context.set_function(name.to_string(), function)?;
+        }
+    }
+    Ok(())
+}
+
+fn helper(name: &str) -> Option<Function> {
+    if name == "round" {
+        return Some(Function::new(round));
+    }
+    let (_, factor, offset) = *CONVERSIONS.iter().find(|(conversion, _, _)| *conversion == name)?;
+    Some(Function::new(move |argument| Ok(Value::Float(argument.as_number()? * factor + offset))))
+}
+
+/// `round(x)` rounds to a whole number like the built-in function, while
+/// `round(x, n)` keeps `n` decimals.
+fn round(argument: &Value) -> EvalexprResult<Value> {
+    let (value, decimals) = match argument {
+        Value::Tuple(arguments) => match arguments.as_slice() {
+            [value, decimals] => (value.as_number()?, decimals.as_int()?),
+            _ => return Err(EvalexprError::wrong_function_argument_amount_range(arguments.len(), 1..=2)),
+        },
+        value => (value.as_number()?, 0),
+    };
+    let scale = 10f64.powi(decimals.clamp(-15, 15) as i32);
+    Ok(Value::Float((value * scale).round() / scale))
+}
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Error, Result};
+use log::error;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::Config;
+use crate::failures::FAILURES;
+use crate::metrics::METRICS;
+use crate::pipeline::{Message, Pipeline, combine_errors};
+use crate::writer::Writers;
+
+const WORKER_QUEUE_SIZE: usize = 100;
+
+/// A fixed set of tasks decoding messages and extracting points concurrently.
+///
+/// Messages are partitioned by topic, so everything published on one topic is
+/// handled by the same worker in arrival order while other topics proceed in
+/// parallel.
+pub struct WorkerPool {
+    senders: Vec<mpsc::Sender<Message>>,
+}
+
+impl WorkerPool {
+    /// Spawns `workers` processing tasks feeding extracted points to `points`.
+    /// When `terminate_on_error` is set the first processing error is sent on
+    /// `fatal` and the worker stops.
+    pub fn spawn(
+        workers: usize,
+        pipeline: Arc<Pipeline>,
+        config: Arc<Config>,
+        points: Arc<Writers>,
+        fatal: mpsc::Sender<Error>,
+    ) -> Self {
+        let terminate_on_error = config.terminate_on_error.unwrap_or(false);
+        let senders = (0..workers.max(1))
+            .map(|_| {
+                let (tx, mut rx) = mpsc::channel::<Message>(WORKER_QUEUE_SIZE);
+                let pipeline = pipeline.clone();
+                let points = points.clone();
+                let fatal = fatal.clone();
+                tokio::spawn(async move {
+                    'messages: while let Some(message) = rx.recv().await {
+                        METRICS.queued_messages.fetch_sub(1, Ordering::Relaxed);
+                        let result = match pipeline.extract(&message) {
+                            Ok(extraction) => {
+                                for point in extraction.points {
+                                    if points.push(point).await.is_err() {
+                                        // the writer has stopped and reported its own error
+                                        break 'messages;
+                                    }
+                                }
+                                combine_errors(extraction.errors)
+                            }
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            error!("Error processing message on {}: {}", message.topic, e);
+                            FAILURES.record(Some(&message.topic), &message.payload, &e);
+                            if terminate_on_error {
+                                let _ = fatal.send(e).await;
+                                break;
+                            }
+                        }
+                    }
+                    // messages left behind by a fatal error are never processed
+                    rx.close();
+                    while rx.try_recv().is_ok() {
+                        METRICS.queued_messages.fetch_sub(1, Ordering::Relaxed);
+                    }
+                });
+                tx
+            })
+            .collect();
+        WorkerPool { senders }
+    }
+
+    /// Queues a message on the worker owning its topic, waiting while that
+    /// worker's queue is full.
+    pub async fn dispatch(&self, message: Message) -> Result<()> {
+        let index = self.worker(&message.topic);
+        METRICS.queued_messages.fetch_add(1, Ordering::Relaxed);
+        self.senders[index].send(message).await.map_err(|_| {
+            METRICS.queued_messages.fetch_sub(1, Ordering::Relaxed);
+            anyhow!("Worker {} has stopped", index)
+        })
+    }
+
+    /// Queues a message like `dispatch`, but hands it back instead of waiting
+    /// when the worker's queue is full.
+    pub fn try_dispatch(&self, message: Message) -> Result<Option<Message>> {
+        let index = self.worker(&message.topic);
+        // counted before sending, so the worker cannot take the message off the gauge first
+        METRICS.queued_messages.fetch_add(1, Ordering::Relaxed);
+        match self.senders[index].try_send(message) {
+            Ok(()) => Ok(None),
+            Err(TrySendError::Full(message)) => {
+                METRICS.queued_messages.fetch_sub(1, Ordering::Relaxed);
+                Ok(Some(message))
+            }
+            Err(TrySendError::Closed(_)) => {
+                METRICS.queued_messages.fetch_sub(1, Ordering::Relaxed);
+                Err(anyhow!("Worker {} has stopped", index))
+            }
+        }
+    }
+
+    fn worker(&self, topic: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        topic.hash(&mut hasher);
+        (hasher.finish() % self.senders.len() as u64) as usize
+    }
+}
@@ -0,0 +1,375 @@
+use anyhow::{anyhow, Error, Result};
+use log::{debug, error, info, warn};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::dead_letter::DeadLetter;
+use crate::disk_buffer::DiskBuffer;
+
+use crate::failures::FAILURES;
+use crate::influx::line_protocol;
+use crate::metrics::METRICS;
+use crate::mqtt::Backoff;
+use crate::pipeline::{Message, Pipeline, Point, combine_errors};
+use crate::queue::{QueueClosed, WriteQueue};
+use crate::sink::Sink;
+use crate::{Config, InfluxTargetConfig};
+
+const WRITE_QUEUE_SIZE: usize = 1000;
+const DEFAULT_RETRY_DELAY_MS: u64 = 1000;
+const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 60000;
+const DEFAULT_FLUSH_INTERVAL_MS: u64 = 1000;
+const REPLAY_INTERVAL: Duration = Duration::from_secs(5);
+const REPLAY_BATCH_SIZE: usize = 5000;
+
+/// Writes points to one sink within the configured retry budget, backing off
+/// exponentially between retries. Writes the sink rejects as invalid are not
+/// retried.
+///
+/// With a disk buffer, points that still fail because InfluxDB is unavailable
+/// are buffered on disk until it is back, and while the buffer holds points
+/// new ones queue up behind them. Other points that still fail once their
+/// retries are used up are appended to the dead-letter file, when one is
+/// configured, rather than being lost.
+pub struct PointWriter {
+    sink: Arc<dyn Sink>,
+    /// The name measurements and `[[sink_routes]]` choose the sink by, None
+    /// for a dry run printing every point
+    sink_name: Option<String>,
+    /// The name of an `[[influxdb_targets]]` entry or other output, for log messages
+    name: Option<String>,
+    bucket: String,
+    retries: u32,
+    retry_delay: Duration,
+    retry_max_delay: Duration,
+    dead_letter: Option<DeadLetter>,
+    disk_buffer: Option<DiskBuffer>,
+}
+
+impl PointWriter {
+    pub fn new(sink: Arc<dyn Sink>, config: &Config, target: &InfluxTargetConfig) -> Result<Self> {
+        Ok(PointWriter {
+            dead_letter: target.dead_letter.as_deref().map(DeadLetter::open).transpose()?,
+            disk_buffer: target.disk_buffer.as_ref().map(DiskBuffer::open).transpose()?,
+            ..PointWriter::plain(sink, config, Some(&target.sink_name()), target.name.clone(), &target.influxdb.bucket)
+        })
+    }
+
+    /// A writer without dead-letter file and disk buffer, for outputs other than InfluxDB.
+    pub fn plain(
+        sink: Arc<dyn Sink>,
+        config: &Config,
+        sink_name: Option<&str>,
+        name: Option<String>,
+        bucket: &str,
+    ) -> Self {
+        PointWriter {
+            sink,
+            sink_name: sink_name.map(str::to_string),
+            name,
+            bucket: bucket.to_string(),
+            retries: config.write_retries.unwrap_or(0),
+            retry_delay: Duration::from_millis(config.write_retry_delay_ms.unwrap_or(DEFAULT_RETRY_DELAY_MS)),
+            retry_max_delay: Duration::from_millis(
+                config.write_retry_max_delay_ms.unwrap_or(DEFAULT_RETRY_MAX_DELAY_MS),
+            ),
+            dead_letter: None,
+            disk_buffer: None,
+        }
+    }
+
+    /// Whether the point is routed to this writer's sink.
+    pub fn accepts(&self, point: &Point) -> bool {
+        match (&self.sink_name, &point.sinks) {
+            (Some(name), Some(sinks)) => sinks.contains(name),
+            _ => true,
+        }
+    }
+
+    pub async fn write(&self, point: &Point) -> Result<()> {
+        self.write_batch(std::slice::from_ref(point)).await
+    }
+
+    /// Writes points sharing a bucket in one request, retrying them together.
+    pub async fn write_batch(&self, points: &[Point]) -> Result<()> {
+        let Some(first) = points.first() else {
+            return Ok(());
+        };
+        for point in points {
+            debug!("Writing measurement: {} {:?}", point.measurement, point.fields);
+        }
+        if let Some(disk_buffer) = &self.disk_buffer
+            && !disk_buffer.is_empty()
+        {
+            // written after the points already buffered, keeping their order
+            return disk_buffer.push(points).or_else(|e| self.give_up(points, e));
+        }
+        let bucket = first.bucket.as_deref().unwrap_or(&self.bucket);
+        let mut attempt = 0;
+        let mut backoff = Backoff::with_limits(self.retry_delay, self.retry_max_delay, true);
+        let error = loop {
+            match self.sink.write_batch(points, bucket).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.retries && self.sink.is_retryable(&e) => {
+                    attempt += 1;
+                    let delay = backoff.next_delay();
+                    warn!(
+                        "Error writing {}, retry {} of {} in {} ms: {}",
+                        self.describe(points),
+                        attempt,
+                        self.retries,
+                        delay.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => break e,
+            }
+        };
+        if let Some(disk_buffer) = &self.disk_buffer
+            && self.sink.is_retryable(&error)
+        {
+            match disk_buffer.push(points) {
+                Ok(()) => {
+                    let what = self.describe(points);
+                    warn!("Error writing {}, buffering points on disk until InfluxDB is back: {}", what, error);
+                    return Ok(());
+                }
+                Err(e) => error!("{}", e),
+            }
+        }
+        self.give_up(points, error)
+    }
+
+    /// What a write holds and, for further targets, where it goes, for log messages.
+    pub fn describe(&self, points: &[Point]) -> String {
+        match &self.name {
+            Some(name) => format!("{} to {}", describe(points), name),
+            None => describe(points),
+        }
+    }
+
+    /// Appends points that could not be written to the dead-letter file, when
+    /// one is configured, returning the error either way.
+    fn give_up(&self, points: &[Point], error: Error) -> Result<()> {
+        if let Some(dead_letter) = &self.dead_letter {
+            for point in points {
+                dead_letter.write(point, &error)?;
+            }
+            return Err(anyhow!("{}, written to the dead letter file", error));
+        }
+        Err(error)
+    }
+
+    /// Writes the points buffered on disk back to InfluxDB, oldest first,
+    /// trying again every few seconds while it is unavailable. Runs forever
+    /// when a disk buffer is configured and returns straight away otherwise.
+    pub async fn replay_disk_buffer(&self) {
+        let Some(disk_buffer) = &self.disk_buffer else {
+            return;
+        };
+        let mut replayed = 0;
+        loop {
+            if disk_buffer.is_empty() {
+                tokio::time::sleep(REPLAY_INTERVAL).await;
+                continue;
+            }
+            let (points, offset) = match disk_buffer.peek(REPLAY_BATCH_SIZE) {
+                Ok(peeked) => peeked,
+                Err(e) => {
+                    error!("{}", e);
+                    tokio::time::sleep(REPLAY_INTERVAL).await;
+                    continue;
+                }
+            };
+            let mut unavailable = false;
+            // points of buckets written before a failure are written again
+            // on the next attempt, which InfluxDB overwrites
+            for batch in by_bucket(points) {
+                let bucket = batch[0].bucket.as_deref().unwrap_or(&self.bucket);
+                match self.sink.write_batch(&batch, bucket).await {
+                    Ok(()) => replayed += batch.len(),
+                    Err(e) if self.sink.is_retryable(&e) => {
+                        debug!("InfluxDB still unavailable, keeping points buffered on disk: {}", e);
+                        unavailable = true;
+                        break;
+                    }
+                    Err(e) => {
+                        let e = self.give_up(&batch, e).unwrap_err();
+                        error!("Error replaying {} from the disk buffer: {}", self.describe(&batch), e);
+                        FAILURES.record(None, lines(&batch).as_bytes(), &e);
+                    }
+                }
+            }
+            if unavailable {
+                tokio::time::sleep(REPLAY_INTERVAL).await;
+                continue;
+            }
+            if let Err(e) = disk_buffer.consume(offset) {
+                error!("{}", e);
+            }
+            if disk_buffer.is_empty() {
+                info!("Replayed {} points from the disk buffer", replayed);
+                replayed = 0;
+            }
+        }
+    }
+}
+
+/// What a write holds, for log messages: the measurement of a single point or
+/// the number of points.
+fn describe(points: &[Point]) -> String {
+    match points {
+        [point] => format!("measurement {}", point.measurement),
+        _ => format!("{} points", points.len()),
+    }
+}
+
+/// Points as line protocol, for the failure history.
+fn lines(points: &[Point]) -> String {
+    points.iter().map(line_protocol).collect::<Vec<_>>().join("\n")
+}
+
+/// Splits a batch into the points of each bucket, keeping their order.
+fn by_bucket(points: Vec<Point>) -> Vec<Vec<Point>> {
+    let mut groups: Vec<Vec<Point>> = Vec::new();
+    for point in points {
+        match groups.iter_mut().find(|group| group[0].bucket == point.bucket) {
+            Some(group) => group.push(point),
+            None => groups.push(vec![point]),
+        }
+    }
+    groups
+}
+
+/// The writer tasks owning all writes, each fed by its own queue.
+///
+/// Points are partitioned by series (measurement plus tag set), so writes for
+/// different series proceed concurrently while the points of any one series
+/// are always written in the order they were queued. Every target has writers
+/// and queues of its own, so one that is slow or down does not hold up the
+/// others until its queues are full.
+pub struct Writers {
+    /// The queues of every target, `config.writers` per target
+    queues: Vec<Arc<WriteQueue>>,
+    per_target: usize,
+    /// The writer of every target, for routing points
+    point_writers: Vec<Arc<PointWriter>>,
+}
+
+impl Writers {
+    /// Spawns `config.writers` writer tasks. Keeping writes off the event loop
+    /// task means slow HTTP requests never delay MQTT keep-alives or
+    /// acknowledgements. Each task writes up to `batch_size` points per
+    /// request, waiting at most `flush_interval_ms` after the first of them for
+    /// the batch to fill. When `terminate_on_error` is set the first write error
+    /// is sent on `fatal` and all writers stop accepting points.
+    pub fn spawn(point_writers: Vec<Arc<PointWriter>>, config: Arc<Config>, fatal: mpsc::Sender<Error>) -> Arc<Self> {
+        let count = config.writers.unwrap_or(1).max(1);
+        // the buffer limits apply to all writers of a target together
+        let max_points = config.buffer_max_points.unwrap_or(WRITE_QUEUE_SIZE).div_ceil(count);
+        let max_bytes = config.buffer_max_bytes.map(|bytes| bytes.div_ceil(count));
+        let overflow = config.buffer_overflow.unwrap_or_default();
+        // added up over the writers of every topic pipeline
+        METRICS.queue_capacity_points.fetch_add(max_points * count * point_writers.len(), Ordering::Relaxed);
+        let writers = Arc::new(Writers {
+            queues: (0..count * point_writers.len())
+                .map(|_| Arc::new(WriteQueue::new(max_points, max_bytes, overflow)))
+                .collect(),
+            per_target: count,
+            point_writers: point_writers.clone(),
+        });
+
+        let terminate_on_error = config.terminate_on_error.unwrap_or(false);
+        let batch_size = config.batch_size.unwrap_or(1).max(1);
+        let flush_interval = Duration::from_millis(config.flush_interval_ms.unwrap_or(DEFAULT_FLUSH_INTERVAL_MS));
+        for index in 0..writers.queues.len() {
+            let writers = writers.clone();
+            let point_writer = point_writers[index / count].clone();
+            let fatal = fatal.clone();
+            tokio::spawn(async move {
+                let points = &writers.queues[index];
+                'points: while let Some(point) = points.pop().await {
+                    let mut batch = vec![point];
+                    let deadline = Instant::now() + flush_interval;
+                    while batch.len() < batch_size {
+                        match tokio::time::timeout_at(deadline, points.pop()).await {
+                            Ok(Some(point)) => batch.push(point),
+                            Ok(None) | Err(_) => break,
+                        }
+                    }
+                    for batch in by_bucket(batch) {
+                        if let Err(e) = point_writer.write_batch(&batch).await {
+                            error!("Error writing {}: {}", point_writer.describe(&batch), e);
+                            FAILURES.record(None, lines(&batch).as_bytes(), &e);
+                            if terminate_on_error {
+                                writers.close();
+                                let _ = fatal.send(e).await;
+                                break 'points;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+        writers
+    }
+
+    /// Queues a point on the writer owning its series, for every target it is routed to.
+    pub async fn push(&self, point: Point) -> Result<(), QueueClosed> {
+        let index = (point.series_key() % self.per_target as u64) as usize;
+        let queues = self.queues.iter().skip(index).step_by(self.per_target).zip(&self.point_writers);
+        // every queue but the last gets a copy of the point
+        let mut last = None;
+        for (queue, point_writer) in queues {
+            if point_writer.accepts(&point)
+                && let Some(previous) = last.replace(queue)
+            {
+                previous.push(point.clone()).await?;
+            }
+        }
+        match last {
+            Some(queue) => queue.push(point).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Number of points waiting for the writers of the target furthest behind.
+    pub fn len(&self) -> usize {
+        self.queues
+            .chunks(self.per_target)
+            .map(|queues| queues.iter().map(|queue| queue.len()).sum())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn close(&self) {
+        for queue in &self.queues {
+            queue.close();
+        }
+    }
+}
+
+/// Extracts the points from one message and writes them straight away to
+/// every target they are routed to, for callers that do not go through the writer task. Every
+/// point is attempted and the errors of all failed writes are reported together.
+pub async fn extract_and_write(
+    pipeline: &Pipeline,
+    message: &Message,
+    point_writers: &[Arc<PointWriter>],
+) -> Result<()> {
+    let extraction = pipeline.extract(message)?;
+    let mut errors = extraction.errors;
+    for point in extraction.points {
+        for point_writer in point_writers.iter().filter(|point_writer| point_writer.accepts(&point)) {
+            if let Err(e) = point_writer.write(&point).await {
+                let what = point_writer.describe(std::slice::from_ref(&point));
+                errors.push(anyhow!("Error writing {}: {}", what, e));
+            }
+        }
+    }
+    combine_errors(errors)
+}
@@ -0,0 +1,46 @@
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+
+/// Converts an XML document to JSON for JSONPath, keyed by the root element's
+/// name. Attributes become members prefixed with `@`, child elements members
+/// named after them (an array when repeated) and text `#text`. An element with
+/// nothing but text becomes that text, so `<temp>21.5</temp>` reads as
+/// `"temp": "21.5"`. Namespace prefixes are dropped.
+pub fn to_json(text: &str) -> Result<Value> {
+    let document = roxmltree::Document::parse(text).map_err(|e| anyhow!("Failed to parse XML payload: {}", e))?;
+    let root = document.root_element();
+    let mut object = Map::new();
+    object.insert(root.tag_name().name().to_string(), element(root));
+    Ok(Value::Object(object))
+}
+
+fn element(node: roxmltree::Node) -> Value {
+    let mut object = Map::new();
+    for attribute in node.attributes() {
+        object.insert(format!("@{}", attribute.name()), Value::String(attribute.value().to_string()));
+    }
+    let mut text = String::new();
+    for child in node.children() {
+        if child.is_element() {
+            let name = child.tag_name().name().to_string();
+            let value = element(child);
+            match object.get_mut(&name) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    object.insert(name, value);
+                }
+            }
+        } else if let Some(fragment) = child.text() {
+            text.push_str(fragment);
+        }
+    }
+    let text = text.trim();
+    if object.is_empty() {
+        return Value::String(text.to_string());
+    }
+    if !text.is_empty() {
+        object.insert("#text".to_string(), Value::String(text.to_string()));
+    }
+    Value::Object(object)
+}